@@ -0,0 +1,170 @@
+//! Compares the rope-backed `Document` with the `Vec<Line>` storage it
+//! replaced, on a generated 100 MB file, and times typing at the end of a
+//! highlighted one. Run with `cargo bench`.
+
+use guard::{documents::Document, editor::SearchOptions, history, terminal::Position};
+use std::{
+    env, fs,
+    time::{Duration, Instant},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+const FILE_SIZE: usize = 100 * 1024 * 1024;
+const LONG_LINE_SIZE: usize = 1024 * 1024;
+const EDITS: usize = 200;
+const VIEW_HEIGHT: usize = 50;
+
+/// The previous storage: one `String` per line, rebuilt grapheme by grapheme
+/// on every insertion, split and joined by shifting the vector.
+struct Lines(Vec<String>);
+
+impl Lines {
+    fn insert(&mut self, y: usize, at: usize, c: char) {
+        let line = &mut self.0[y];
+        let mut result = String::new();
+        for (index, grapheme) in line.graphemes(true).enumerate() {
+            if index == at {
+                result.push(c);
+            }
+            result.push_str(grapheme);
+        }
+        *line = result;
+    }
+    fn split(&mut self, y: usize, at: usize) {
+        let (mut head, mut tail) = (String::new(), String::new());
+        for (index, grapheme) in self.0[y].graphemes(true).enumerate() {
+            if index < at {
+                head.push_str(grapheme);
+            } else {
+                tail.push_str(grapheme);
+            }
+        }
+        self.0[y] = head;
+        self.0.insert(y + 1, tail);
+    }
+    fn join(&mut self, y: usize) {
+        let next = self.0.remove(y + 1);
+        self.0[y] = format!("{}{}", self.0[y], next);
+    }
+}
+
+fn time(run: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    run();
+    start.elapsed()
+}
+
+fn report(name: &str, previous: Duration, rope: Duration) {
+    println!(
+        "{name:<40} {:>10.1?} {:>10.1?} {:>8.1}x",
+        previous,
+        rope,
+        previous.as_secs_f64() / rope.as_secs_f64().max(1e-9),
+    );
+}
+
+fn report_time(name: &str, rope: Duration) {
+    println!("{name:<40} {:>10} {:>10.1?}", "", rope);
+}
+
+fn write_file(name: &str, contents: &str) -> String {
+    let path = env::temp_dir().join(name);
+    fs::write(&path, contents).expect("cannot write benchmark file");
+    path.to_string_lossy().into_owned()
+}
+
+fn main() {
+    let line = "let value = some_function(argument, another_argument); // comment\n";
+    let contents = line.repeat(FILE_SIZE / line.len());
+    let lines = contents.lines().count();
+    let middle = lines / 2;
+    let path = write_file("guard-bench-lines.txt", &contents);
+
+    let long_line = format!("{}\n", "x".repeat(LONG_LINE_SIZE));
+    let long_path = write_file("guard-bench-long-line.txt", &long_line);
+
+    println!(
+        "{:<40} {:>10} {:>10} {:>9}",
+        "", "Vec<Line>", "rope", "speedup"
+    );
+
+    let mut previous = None;
+    let previous_time = time(|| {
+        let contents = fs::read_to_string(&path).unwrap();
+        history::content_hash(contents.as_bytes());
+        previous = Some(Lines(contents.lines().map(String::from).collect()));
+    });
+    let mut previous = previous.unwrap();
+    let mut document = None;
+    let rope_time = time(|| document = Some(Document::open(&path).unwrap()));
+    let mut document = document.unwrap();
+    report(&format!("open {} lines", lines), previous_time, rope_time);
+
+    let previous_time = time(|| {
+        for _ in 0..EDITS {
+            previous.split(middle, 10);
+            previous.join(middle);
+        }
+    });
+    let rope_time = time(|| {
+        let at = Position { x: 10, y: middle };
+        for _ in 0..EDITS {
+            document.insert(&at, '\n');
+            document.delete(&at);
+        }
+    });
+    report(
+        &format!("{EDITS} line splits and joins mid-file"),
+        previous_time,
+        rope_time,
+    );
+
+    let mut previous = Lines(vec![long_line.trim_end().to_string()]);
+    let mut document = Document::open(&long_path).unwrap();
+    let previous_time = time(|| {
+        for x in 0..EDITS {
+            previous.insert(0, LONG_LINE_SIZE / 2 + x, 'y');
+        }
+    });
+    let rope_time = time(|| {
+        for x in 0..EDITS {
+            document.insert(
+                &Position {
+                    x: LONG_LINE_SIZE / 2 + x,
+                    y: 0,
+                },
+                'y',
+            );
+        }
+    });
+    report(
+        &format!("{EDITS} keystrokes in a 1 MB line"),
+        previous_time,
+        rope_time,
+    );
+
+    // The same lines as Rust, highlighted as the screen draws them: the last
+    // rows once the cursor jumps to the end, then again after every key.
+    let rust_path = write_file("guard-bench-lines.rs", &contents);
+    let mut document = None;
+    let open_time = time(|| document = Some(Document::open(&rust_path).unwrap()));
+    let mut document = document.unwrap();
+    let end = document.len();
+    let view = end.saturating_sub(VIEW_HEIGHT)..end;
+    let jump_time = time(|| {
+        document.highlight(&None, &SearchOptions::default(), view.clone());
+    });
+    let type_time = time(|| {
+        for x in 0..EDITS {
+            document.insert(&Position { x, y: end - 1 }, 'y');
+            document.highlight(&None, &SearchOptions::default(), view.clone());
+        }
+    });
+    report_time("open a highlighted file", open_time);
+    report_time("highlight its last rows", jump_time);
+    report_time(&format!("{EDITS} keystrokes at its end"), type_time);
+
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(long_path);
+    let _ = fs::remove_file(rust_path);
+}
@@ -0,0 +1,228 @@
+use std::path::Path;
+
+pub const VERSION: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+pub const USAGE: &str = "\
+Usage: guard [OPTIONS] [[+LINE[:COLUMN]] FILE[:LINE[:COLUMN]]]...
+
+Opens each FILE in a buffer of its own. A FILE of - reads a buffer from
+standard input.
+
+Options:
+  +LINE[:COLUMN]  Put the cursor of the next FILE at LINE and COLUMN
+  -R              Open the files read-only
+  -h, --help      Print this help and exit
+  -V, --version   Print the version and exit
+  --              Take every later argument as a FILE
+";
+
+/// A file named on the command line and where to put the cursor in it.
+#[derive(Default)]
+pub struct FileArgument {
+    /// `None` for `-`, standard input.
+    pub file_name: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct Arguments {
+    pub files: Vec<FileArgument>,
+    pub read_only: bool,
+}
+
+pub enum Command {
+    Edit(Arguments),
+    Help,
+    Version,
+}
+
+/// Parses the command line, without the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut arguments = Arguments::default();
+    let mut position = None;
+    let mut only_files = false;
+
+    for arg in args {
+        if !only_files {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "-V" | "--version" => return Ok(Command::Version),
+                "-R" => {
+                    arguments.read_only = true;
+                    continue;
+                }
+                "--" => {
+                    only_files = true;
+                    continue;
+                }
+                _ => (),
+            }
+            if let Some(at) = arg.strip_prefix('+') {
+                position =
+                    Some(parse_position(at).ok_or_else(|| format!("invalid position: {arg}"))?);
+                continue;
+            }
+            if arg.len() > 1 && arg.starts_with('-') {
+                return Err(format!("unknown option: {arg}"));
+            }
+        }
+
+        let mut file = if arg == "-" && !only_files {
+            if arguments.files.iter().any(|file| file.file_name.is_none()) {
+                return Err(String::from("standard input can only be read once"));
+            }
+            FileArgument::default()
+        } else {
+            file_argument(&arg)
+        };
+        if let Some((line, column)) = position.take() {
+            file.line = Some(line);
+            file.column = column;
+        }
+        arguments.files.push(file);
+    }
+    if position.is_some() {
+        return Err(String::from("a +LINE position needs a file after it"));
+    }
+    Ok(Command::Edit(arguments))
+}
+
+/// Parses `LINE` or `LINE:COLUMN`.
+fn parse_position(at: &str) -> Option<(usize, Option<usize>)> {
+    let mut parts = at.splitn(2, ':');
+    let line = parts.next()?.parse().ok()?;
+    let column = match parts.next() {
+        Some(column) => Some(column.parse().ok()?),
+        None => None,
+    };
+    Some((line, column))
+}
+
+/// Splits `FILE:LINE` and `FILE:LINE:COLUMN`, as compilers and grep print
+/// them, with or without a trailing colon. A file whose name really ends
+/// like that is taken as it is.
+pub fn file_argument(arg: &str) -> FileArgument {
+    let whole = FileArgument {
+        file_name: Some(arg.to_string()),
+        ..FileArgument::default()
+    };
+    if Path::new(arg).exists() {
+        return whole;
+    }
+    let is_number = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let trimmed = arg.strip_suffix(':').unwrap_or(arg);
+    let Some((rest, last)) = trimmed.rsplit_once(':').filter(|(_, last)| is_number(last)) else {
+        return whole;
+    };
+    let (file_name, line, column) = match rest.rsplit_once(':') {
+        Some((file_name, line)) if is_number(line) => (file_name, line, Some(last)),
+        _ => (rest, last, None),
+    };
+    if file_name.is_empty() {
+        return whole;
+    }
+    FileArgument {
+        file_name: Some(file_name.to_string()),
+        line: line.parse().ok(),
+        column: column.and_then(|column| column.parse().ok()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn parse_args(args: &[&str]) -> Result<Command, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn files(args: &[&str]) -> Vec<(Option<String>, Option<usize>, Option<usize>)> {
+        match parse_args(args) {
+            Ok(Command::Edit(arguments)) => arguments
+                .files
+                .into_iter()
+                .map(|file| (file.file_name, file.line, file.column))
+                .collect(),
+            _ => panic!("{args:?} did not parse as files to edit"),
+        }
+    }
+
+    fn file(
+        name: &str,
+        line: Option<usize>,
+        column: Option<usize>,
+    ) -> (Option<String>, Option<usize>, Option<usize>) {
+        (Some(name.to_string()), line, column)
+    }
+
+    #[test]
+    fn plus_position_applies_to_the_next_file() {
+        assert_eq!(
+            files(&["+3:7", "a.rs", "b.rs", "+12", "c.rs"]),
+            [
+                file("a.rs", Some(3), Some(7)),
+                file("b.rs", None, None),
+                file("c.rs", Some(12), None),
+            ]
+        );
+        assert!(parse_args(&["+x", "a.rs"]).is_err());
+        assert!(parse_args(&["+3:", "a.rs"]).is_err());
+        assert!(parse_args(&["a.rs", "+3"]).is_err());
+    }
+
+    #[test]
+    fn file_positions_are_split_off() {
+        assert_eq!(
+            files(&["missing.rs:12:5:", "missing.rs:8", "missing.rs:8:"]),
+            [
+                file("missing.rs", Some(12), Some(5)),
+                file("missing.rs", Some(8), None),
+                file("missing.rs", Some(8), None),
+            ]
+        );
+        assert_eq!(files(&[":12"]), [file(":12", None, None)]);
+        assert_eq!(files(&["missing.rs:x"]), [file("missing.rs:x", None, None)]);
+    }
+
+    #[test]
+    fn existing_file_names_with_colons_are_kept() {
+        let dir = env::temp_dir().join(format!("guard-arguments-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let name = dir.join("notes:3").to_string_lossy().into_owned();
+        fs::write(&name, "").unwrap();
+
+        assert_eq!(files(&[&name]), [file(&name, None, None)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn standard_input_is_read_once() {
+        assert_eq!(
+            files(&["-", "a.rs"]),
+            [(None, None, None), file("a.rs", None, None)]
+        );
+        assert_eq!(
+            parse_args(&["-", "-"]).err().as_deref(),
+            Some("standard input can only be read once")
+        );
+    }
+
+    #[test]
+    fn options_end_at_double_dash() {
+        assert_eq!(
+            files(&["--", "-R", "-", "--help"]),
+            [
+                file("-R", None, None),
+                file("-", None, None),
+                file("--help", None, None)
+            ]
+        );
+        assert!(
+            matches!(parse_args(&["-R", "a.rs"]), Ok(Command::Edit(arguments)) if arguments.read_only)
+        );
+        assert!(matches!(parse_args(&["a.rs", "--help"]), Ok(Command::Help)));
+        assert!(matches!(parse_args(&["-V"]), Ok(Command::Version)));
+        assert!(parse_args(&["-x"]).is_err());
+    }
+}
@@ -1,158 +1,1560 @@
-use std::{char, fs, io::Write};
+use encoding_rs::{Encoder, EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use regex::Regex;
+use ropey::{Rope, RopeSlice};
+use std::{
+    borrow::Cow,
+    cmp,
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    iter,
+    ops::Range,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime},
+};
+
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete, UnicodeSegmentation};
 
 use crate::{
-    editor::SearchDirection,
+    editor::{SearchDirection, SearchOptions},
     files::FileType,
-    lines::{self, Line},
+    hex::HexBuffer,
+    history::{self, Edit, History},
+    large::{LargeFile, LARGE_FILE_SIZE},
+    lines::Line,
+    swap::Swap,
     terminal::Position,
 };
 
-#[derive(Default)]
+/// How many lines apart the multiline comment state is remembered, so
+/// highlighting a row only scans for comments from the checkpoint above it.
+const COMMENT_CHECKPOINT_LINES: usize = 256;
+/// Past this many highlighted rows, those far from the rows just drawn are
+/// dropped.
+const MAX_HIGHLIGHTED_LINES: usize = 1024;
+
+/// A highlighted row and the comment state it was highlighted for.
+struct HighlightedLine {
+    line: Line,
+    start_with_comment: bool,
+    end_in_comment: bool,
+    has_matches: bool,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        }
+    }
+}
+
+/// The buffer is a rope holding every line followed by `\n`, so splitting and
+/// joining lines are edits in the middle of the rope instead of shifting a
+/// vector. `lines` caches the highlighted rows around the ones drawn, and
+/// `comment_checkpoints` whether every `COMMENT_CHECKPOINT_LINES`th line
+/// starts inside a multiline comment; both are cut back from where the text
+/// changes.
+/// The file's encoding, line endings, final newline and BOM are kept apart
+/// from the text and put back on save.
 pub struct Document {
-    pub lines: Vec<Line>,
+    text: Rope,
+    lines: BTreeMap<usize, HighlightedLine>,
+    comment_checkpoints: Vec<bool>,
     pub file_name: Option<String>,
     pub is_dirty: bool,
     pub file_type: FileType,
+    pub history: History,
+    pub content_hash: Option<u64>,
+    pub line_ending: LineEnding,
+    saved_line_ending: LineEnding,
+    /// Set for files that mix line endings: whether each line ends in CRLF,
+    /// so every line is written back with the ending it came with. Lines
+    /// added since get `line_ending`.
+    pub crlf_lines: Option<Vec<bool>>,
+    saved_mixed: bool,
+    pub missing_final_newline: bool,
+    pub bom: bool,
+    pub encoding: &'static Encoding,
+    /// Set for binary files, which are edited as raw bytes in the hex view
+    /// instead of as text.
+    pub hex: Option<HexBuffer>,
+    /// Set for large files until their first edit: the lines are read from
+    /// disk as they are shown and the rope stays empty.
+    pub large: Option<LargeFile>,
+    pub swap: Option<Swap>,
+    /// Refuses edits and saves, for files that are not writable or opened
+    /// with `-R`.
+    pub read_only: bool,
+    changes: usize,
+    disk_state: Option<DiskState>,
+}
+
+/// The file on disk as it was last opened, saved or checked, to notice when
+/// another program rewrites it.
+#[derive(Clone, Copy, PartialEq)]
+struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    /// Not known for large files that have not been read whole.
+    hash: Option<u64>,
+}
+
+impl DiskState {
+    fn read(file_name: &str, hash: Option<u64>) -> Option<Self> {
+        let metadata = fs::metadata(file_name).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash,
+        })
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            text: Rope::default(),
+            lines: BTreeMap::new(),
+            comment_checkpoints: Vec::new(),
+            file_name: None,
+            is_dirty: false,
+            file_type: FileType::default(),
+            history: History::default(),
+            content_hash: None,
+            line_ending: LineEnding::default(),
+            saved_line_ending: LineEnding::default(),
+            crlf_lines: None,
+            saved_mixed: false,
+            missing_final_newline: false,
+            bom: false,
+            encoding: UTF_8,
+            hex: None,
+            large: None,
+            swap: None,
+            read_only: false,
+            changes: 0,
+            disk_state: None,
+        }
+    }
 }
 
 impl Document {
+    /// An empty buffer that will be saved as `file_name`.
+    pub fn new(file_name: &str) -> Self {
+        Self {
+            file_name: Some(file_name.to_string()),
+            file_type: FileType::from(file_name),
+            swap: Swap::open(file_name, ""),
+            ..Self::default()
+        }
+    }
     pub fn open(file_name: &str) -> Result<Self, std::io::Error> {
-        let contents = fs::read_to_string(file_name)?;
+        Self::open_with_encoding(file_name, None)
+    }
+    /// Opens `file_name` decoded as `encoding`, or as the encoding named by
+    /// its BOM or guessed from its contents when `encoding` is `None`.
+    pub fn open_with_encoding(
+        file_name: &str,
+        encoding: Option<&'static Encoding>,
+    ) -> Result<Self, std::io::Error> {
+        let large = match encoding {
+            Some(_) => None,
+            None => Self::open_large(file_name)?,
+        };
+        let mut document = match large {
+            Some(document) => document,
+            None => Self::read(file_name, encoding)?,
+        };
+        document.read_only = !is_writable(file_name);
+        Ok(document)
+    }
+    /// Opens `file_name` without reading it whole if it is at least
+    /// `LARGE_FILE_SIZE` bytes of UTF-8 text, judging by its start. Other
+    /// large files are read like any file.
+    fn open_large(file_name: &str) -> Result<Option<Self>, std::io::Error> {
+        if fs::metadata(file_name)?.len() < LARGE_FILE_SIZE {
+            return Ok(None);
+        }
+        let mut sample = Vec::new();
+        fs::File::open(file_name)?
+            .take(64 * 1024)
+            .read_to_end(&mut sample)?;
+        let bom_length = match Encoding::for_bom(&sample) {
+            Some((encoding, length)) if encoding == UTF_8 => length,
+            Some(_) => return Ok(None),
+            None => 0,
+        };
+        let is_utf8 = match std::str::from_utf8(&sample[bom_length..]) {
+            Ok(_) => true,
+            // The sample may end in the middle of a character.
+            Err(err) => err.error_len().is_none(),
+        };
+        if !is_utf8 || looks_binary(&sample[bom_length..]) {
+            return Ok(None);
+        }
+
+        let crlf_count = memchr::memmem::find_iter(&sample, b"\r\n").count();
+        let line_ending = if crlf_count * 2 > memchr::memchr_iter(b'\n', &sample).count() {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        };
+        Ok(Some(Self {
+            large: Some(LargeFile::open(file_name, bom_length as u64)?),
+            file_name: Some(file_name.to_string()),
+            file_type: FileType::from(file_name),
+            line_ending,
+            saved_line_ending: line_ending,
+            bom: bom_length > 0,
+            disk_state: DiskState::read(file_name, None),
+            ..Self::default()
+        }))
+    }
+    /// Reads `file_name` whole, see `open_with_encoding`.
+    fn read(file_name: &str, encoding: Option<&'static Encoding>) -> Result<Self, std::io::Error> {
+        let mut bytes = fs::read(file_name)?;
         let file_type = FileType::from(file_name);
 
-        let mut lines = Vec::new();
-        for line in contents.lines() {
-            lines.push(Line::from(line));
+        let content_hash = history::content_hash(&bytes);
+
+        let (detected, bom_length) = detect_encoding(&bytes);
+        let is_guessed = encoding.is_none() && bom_length == 0;
+        if is_guessed && detected != UTF_16LE && detected != UTF_16BE && looks_binary(&bytes) {
+            return Ok(Self::binary(file_name, bytes, content_hash));
+        }
+        let is_detected = encoding.is_none_or(|encoding| encoding == detected);
+        let bom = bom_length > 0 && is_detected;
+        let encoding = encoding.unwrap_or(detected);
+        if bom {
+            bytes.drain(..bom_length);
         }
+        let mut contents = if encoding == UTF_8 {
+            String::from_utf8(bytes).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?
+        } else {
+            let (contents, had_errors) = encoding.decode_without_bom_handling(&bytes);
+            let contents = (!had_errors).then(|| contents.into_owned());
+            match contents {
+                Some(contents) => contents,
+                // Not text in the encoding we guessed, so treat it as binary.
+                None if is_guessed => return Ok(Self::binary(file_name, bytes, content_hash)),
+                None => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("not valid {}", encoding.name()),
+                    ))
+                }
+            }
+        };
+        // A stored undo history refers to the text as decoded when it was
+        // written, so it only applies to the detected encoding.
+        let history = if is_detected {
+            History::restore(file_name, content_hash).unwrap_or_default()
+        } else {
+            History::default()
+        };
+
+        let (line_ending, crlf_lines, missing_final_newline) =
+            normalize_line_endings(&mut contents);
         Ok(Self {
-            lines,
+            swap: Swap::open(file_name, &contents),
+            text: Rope::from_str(&contents),
+            lines: BTreeMap::new(),
+            comment_checkpoints: Vec::new(),
             file_name: Some(file_name.to_string()),
             is_dirty: false,
             file_type,
+            history,
+            content_hash: Some(content_hash),
+            line_ending,
+            saved_line_ending: line_ending,
+            saved_mixed: crlf_lines.is_some(),
+            crlf_lines,
+            missing_final_newline,
+            bom,
+            encoding,
+            hex: None,
+            large: None,
+            read_only: false,
+            changes: 0,
+            disk_state: DiskState::read(file_name, Some(content_hash)),
         })
     }
-    pub fn insert_new_line(&mut self, at: &Position) -> Result<(), Box<dyn std::error::Error>> {
-        if at.y > self.lines.len() {
-            return Err("Position is larget than the line length!".into());
+    /// An unnamed buffer holding `bytes`, such as text piped in on standard
+    /// input. Bytes that are not valid in the guessed encoding are replaced.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let (encoding, bom_length) = detect_encoding(bytes);
+        let (contents, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        let mut contents = contents.into_owned();
+        let (line_ending, crlf_lines, missing_final_newline) =
+            normalize_line_endings(&mut contents);
+        Self {
+            text: Rope::from_str(&contents),
+            line_ending,
+            saved_line_ending: line_ending,
+            saved_mixed: crlf_lines.is_some(),
+            crlf_lines,
+            missing_final_newline,
+            bom: bom_length > 0,
+            encoding,
+            ..Self::default()
         }
-
-        if at.y == self.lines.len() {
-            self.lines.push(Line::default());
-            return Ok(());
+    }
+    /// A binary file, shown and edited in the hex view.
+    fn binary(file_name: &str, bytes: Vec<u8>, content_hash: u64) -> Self {
+        Self {
+            file_name: Some(file_name.to_string()),
+            file_type: FileType::from(file_name),
+            content_hash: Some(content_hash),
+            hex: Some(HexBuffer { bytes }),
+            disk_state: DiskState::read(file_name, Some(content_hash)),
+            ..Self::default()
+        }
+    }
+    /// Reads a large file whole into the rope, as needed before the first
+    /// edit, and reports whether the text is in the rope. It stays as it
+    /// was if the file cannot be read.
+    fn load(&mut self) -> bool {
+        let (Some(_), Some(file_name)) = (&self.large, &self.file_name) else {
+            return true;
+        };
+        match Self::read(file_name, None) {
+            Ok(document) => {
+                let read_only = self.read_only;
+                *self = Self {
+                    read_only,
+                    ..document
+                };
+                true
+            }
+            Err(_) => false,
+        }
+    }
+    /// Whether the buffer is too large to be highlighted or swapped.
+    pub fn is_large(&self) -> bool {
+        self.large.is_some() || self.text.len_bytes() as u64 >= LARGE_FILE_SIZE
+    }
+    /// Overwrites the nibble at `at` in the hex view and reports whether
+    /// there was one.
+    pub fn set_nibble(&mut self, at: &Position, value: u8) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let changed = self
+            .hex
+            .as_mut()
+            .is_some_and(|hex| hex.set_nibble(at, value));
+        if changed {
+            self.is_dirty = true;
+        }
+        changed
+    }
+    /// Number of lines in the buffer.
+    pub fn len(&self) -> usize {
+        if let Some(large) = &self.large {
+            return large.len();
+        }
+        self.text.len_lines().saturating_sub(1)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// The text of line `y` without its line break.
+    fn line_slice(&self, y: usize) -> RopeSlice<'_> {
+        let line = self.text.line(y);
+        let length = line.len_chars();
+        if length > 0 && line.char(length - 1) == '\n' {
+            line.slice(..length - 1)
+        } else {
+            line
+        }
+    }
+    /// Length of line `y` in graphemes, 0 past the end of the buffer.
+    pub fn line_length(&self, y: usize) -> usize {
+        if self.large.is_some() {
+            return self.line(y).map_or(0, |line| line.length);
+        }
+        if y >= self.len() {
+            return 0;
+        }
+        grapheme_to_char(self.line_slice(y), usize::MAX).1
+    }
+    /// Copies line `y` out of the rope, or reads it from a large file,
+    /// without highlighting.
+    pub fn line(&self, y: usize) -> Option<Line> {
+        if let Some(large) = &self.large {
+            return large.line(y).map(|line| Line::from(line.as_str()));
+        }
+        if y >= self.len() {
+            return None;
+        }
+        Some(Line::from(String::from(self.line_slice(y)).as_str()))
+    }
+    /// Renders graphemes `start..end` of line `y`, highlighted if the line has
+    /// been highlighted already.
+    pub fn render_line(&self, y: usize, start: usize, end: usize) -> Option<String> {
+        match self.lines.get(&y) {
+            Some(highlighted) => Some(highlighted.line.render(start, end)),
+            None => self.line(y).map(|line| line.render(start, end)),
         }
-
-        let current_line = &mut self.lines[at.y];
-        let new_line = current_line.split(at.x);
-        self.lines.insert(at.y + 1, new_line);
-        Ok(())
+    }
+    /// Char index in the rope of the grapheme at `at`, clamped to the line.
+    fn char_index(&self, at: &Position) -> usize {
+        if at.y >= self.len() {
+            return self.text.len_chars();
+        }
+        self.text.line_to_char(at.y) + grapheme_to_char(self.line_slice(at.y), at.x).0
     }
     pub fn insert(&mut self, at: &Position, c: char) {
-        if at.y > self.lines.len() {
+        if self.read_only || !self.load() || at.y > self.len() {
             return;
         }
 
-        self.is_dirty = true;
-
-        if c == '\n' {
-            self.insert_new_line(at);
-        } else if at.y == self.lines.len() {
-            let mut line = Line::default();
-            line.insert(0, c);
-            self.lines.push(line);
-        } else {
-            let line = &mut self.lines[at.y];
-            line.insert(at.x, c);
+        // Typing on the empty line past the end of the buffer is recorded as
+        // a line break after the last line, so undo removes that line again.
+        // In an empty buffer the line break comes first, as an edit of its
+        // own, so what is typed next still merges with the character.
+        let mut edits = Vec::new();
+        if self.is_empty() {
+            edits.push(Edit::Insert {
+                at: Position::default(),
+                text: String::from("\n"),
+            });
+        }
+        let edit = match at.y.checked_sub(1) {
+            None if c == '\n' && !edits.is_empty() => None,
+            Some(y) if at.y == self.len() => Some(Edit::Insert {
+                at: Position {
+                    x: self.line_length(y),
+                    y,
+                },
+                text: if c == '\n' {
+                    String::from("\n")
+                } else {
+                    format!("\n{c}")
+                },
+            }),
+            _ => Some(Edit::Insert {
+                at: *at,
+                text: c.to_string(),
+            }),
+        };
+        edits.extend(edit);
+        for edit in &edits {
+            self.apply(edit);
+        }
+        self.record(edits);
+    }
+    /// Applies `edit` without recording it and returns where the cursor
+    /// should go afterwards.
+    fn apply(&mut self, edit: &Edit) -> Position {
+        self.changes += 1;
+        let len = self.len();
+        match edit {
+            Edit::Insert { at, text } => {
+                let index = self.char_index(at);
+                self.text.insert(index, text);
+                self.unhighlight_lines(at.y);
+            }
+            Edit::Delete { at, text } => {
+                let start = self.char_index(at);
+                let end = cmp::min(start + text.chars().count(), self.text.len_chars());
+                self.text.remove(start..end);
+                self.unhighlight_lines(at.y);
+            }
+        }
+        if let Some(crlf_lines) = &mut self.crlf_lines {
+            // A split line keeps its ending on its last part, and lines
+            // joined together keep the ending of the last of them.
+            let (Edit::Insert { at, .. } | Edit::Delete { at, .. }) = edit;
+            let at = cmp::min(at.y, crlf_lines.len());
+            let new_len = self.text.len_lines().saturating_sub(1);
+            if new_len > len {
+                let is_crlf = self.line_ending == LineEnding::CrLf;
+                crlf_lines.splice(at..at, iter::repeat_n(is_crlf, new_len - len));
+            } else {
+                let end = cmp::min(at + (len - new_len), crlf_lines.len());
+                crlf_lines.drain(at..end);
+            }
+        }
+        edit.end()
+    }
+    fn record(&mut self, edits: Vec<Edit>) {
+        self.history.record(edits);
+        self.update_dirty();
+    }
+    /// Applies edits handed out by the history and returns the cursor
+    /// position to restore.
+    fn apply_history(&mut self, edits: Option<Vec<Edit>>) -> Option<Position> {
+        let cursor = edits?.iter().fold(None, |_, edit| Some(self.apply(edit)));
+        self.update_dirty();
+        cursor
+    }
+    fn update_dirty(&mut self) {
+        self.is_dirty = self.history.is_dirty()
+            || self.line_ending != self.saved_line_ending
+            || self.crlf_lines.is_some() != self.saved_mixed;
+    }
+    /// Makes every line end in `line_ending` when the buffer is saved.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if self.read_only || !self.load() {
+            return;
         }
-        self.unhighlight_lines(at.y);
+        self.line_ending = line_ending;
+        self.crlf_lines = None;
+        self.update_dirty();
     }
+    pub fn undo(&mut self) -> Option<Position> {
+        if self.read_only {
+            return None;
+        }
+        let edits = self.history.undo();
+        self.apply_history(edits)
+    }
+    pub fn redo(&mut self) -> Option<Position> {
+        if self.read_only {
+            return None;
+        }
+        let edits = self.history.redo();
+        self.apply_history(edits)
+    }
+    pub fn switch_branch(&mut self, forward: bool) -> Option<Position> {
+        if self.read_only {
+            return None;
+        }
+        let edits = self.history.switch_branch(forward);
+        self.apply_history(edits)
+    }
+    pub fn time_travel(&mut self, seconds: u64, earlier: bool) -> Option<Position> {
+        if self.read_only {
+            return None;
+        }
+        let edits = self.history.time_travel(seconds, earlier);
+        self.apply_history(edits)
+    }
+    pub fn travel_to(&mut self, id: usize) -> Option<Position> {
+        if self.read_only {
+            return None;
+        }
+        let edits = self.history.travel_to(id);
+        self.apply_history(Some(edits))
+    }
+    /// Forgets the highlighting from line `start` down, after the text or
+    /// the file type changed there.
     pub fn unhighlight_lines(&mut self, start: usize) {
         let start = start.saturating_sub(1);
-        for line in self.lines.iter_mut().skip(start) {
-            line.is_highlighted = false;
-        }
+        self.lines.split_off(&start);
+        self.comment_checkpoints
+            .truncate(start / COMMENT_CHECKPOINT_LINES + 1);
     }
     pub fn save(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.read_only {
+            return Err("the buffer is read-only".into());
+        }
+        self.force_save()
+    }
+    /// Saves the buffer even when it is read-only. The new contents are
+    /// renamed over the file, so a write-protected file is replaced as long
+    /// as its directory is writable; `save_with_sudo` is for the rest.
+    pub fn force_save(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.load() {
+            return Err("could not read the whole file".into());
+        }
+        let Some(file_name) = &self.file_name else {
+            return Err("the buffer has no file name".into());
+        };
+        let path = resolve_symlinks(Path::new(file_name))?;
+        let content_hash = if let Some(hex) = &self.hex {
+            write_atomically(&path, iter::once(Ok(Cow::Borrowed(hex.bytes.as_slice()))))?;
+            history::content_hash(&hex.bytes)
+        } else {
+            write_atomically(&path, self.disk_chunks())?;
+            self.disk_chunks()
+                .try_fold(history::content_hash(&[]), |hash, chunk| {
+                    chunk.map(|chunk| history::extend_hash(hash, &chunk))
+                })?
+        };
+        self.finish_save(content_hash);
+        Ok(())
+    }
+    /// Saves the buffer by piping it to `sudo tee`, which keeps the file's
+    /// owner and permissions. sudo may ask for a password, so the terminal
+    /// has to be handed back to it first.
+    pub fn save_with_sudo(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.load() {
+            return Err("could not read the whole file".into());
+        }
+        let Some(file_name) = &self.file_name else {
+            return Err("the buffer has no file name".into());
+        };
+        let bytes = match &self.hex {
+            Some(hex) => hex.bytes.clone(),
+            None => self
+                .disk_chunks()
+                .try_fold(Vec::new(), |mut bytes, chunk| {
+                    bytes.extend_from_slice(&chunk?);
+                    Ok::<_, io::Error>(bytes)
+                })?,
+        };
+        let mut child = process::Command::new("sudo")
+            .args(["tee", "--", file_name])
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(&bytes)?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(format!("sudo tee failed ({status})").into());
+        }
+        self.finish_save(history::content_hash(&bytes));
+        Ok(())
+    }
+    /// Records that the buffer, whose bytes on disk hash to `content_hash`,
+    /// has just been written.
+    fn finish_save(&mut self, content_hash: u64) {
         if let Some(file_name) = &self.file_name {
-            let mut file = std::fs::File::create(file_name).unwrap();
             self.file_type = FileType::from(file_name);
-
-            for line in &mut self.lines {
-                file.write_all(line.string.as_bytes()).unwrap();
-                file.write_all(b"\n").unwrap();
-            }
-            self.is_dirty = false;
-            return Ok(());
+            self.disk_state = DiskState::read(file_name, Some(content_hash));
+        }
+        self.content_hash = Some(content_hash);
+        self.saved_line_ending = self.line_ending;
+        self.saved_mixed = self.crlf_lines.is_some();
+        self.history.mark_saved();
+        self.is_dirty = false;
+        if let Some(swap) = &mut self.swap {
+            swap.remove();
+        }
+        self.persist_history();
+    }
+    /// Saves the buffer as `file_name` and keeps editing it under that name,
+    /// highlighted for its file type. The old name stays if the save fails.
+    pub fn save_as(&mut self, file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        // A large file is read from its old name.
+        if !self.load() {
+            return Err("could not read the whole file".into());
+        }
+        // A copy may be saved from a read-only buffer.
+        let previous = self.file_name.replace(file_name.to_string());
+        if let Err(err) = self.force_save() {
+            self.file_name = previous;
+            return Err(err);
+        }
+        if previous.as_deref() != Some(file_name) {
+            self.swap = Swap::open(file_name, &self.text());
+            self.unhighlight_lines(0);
         }
         Ok(())
     }
-
-    pub fn highlight(&mut self, word: &Option<String>, until: &Option<usize>) {
-        let mut start_comment = false;
-        let until: usize = if let Some(until) = until {
-            if until.saturating_add(1) < self.lines.len() {
-                until.saturating_add(1)
-            } else {
-                self.lines.len()
+    /// Whether another program changed the file since it was opened, saved
+    /// or last kept. The contents are only read again when the modification
+    /// time or size moved, and a rewrite with the same contents is no change.
+    pub fn changed_on_disk(&mut self) -> bool {
+        let (Some(file_name), Some(state)) = (&self.file_name, self.disk_state) else {
+            return false;
+        };
+        let Some(current) = DiskState::read(file_name, state.hash) else {
+            return false;
+        };
+        if current == state {
+            return false;
+        }
+        // Without a hash to compare, a new time or size is taken as a change.
+        if state.hash.is_none() {
+            return true;
+        }
+        let Ok(bytes) = fs::read(file_name) else {
+            return false;
+        };
+        if Some(history::content_hash(&bytes)) != state.hash {
+            return true;
+        }
+        self.disk_state = Some(current);
+        false
+    }
+    /// Keeps the buffer over the file changed on disk, so the change is not
+    /// reported again and the next save overwrites it.
+    pub fn keep_over_disk(&mut self) {
+        // The stored history stays tied to the contents the buffer was
+        // opened from, so only the state used to notice changes moves on.
+        if let Some(file_name) = &self.file_name {
+            if let Ok(bytes) = fs::read(file_name) {
+                let hash = history::content_hash(&bytes);
+                self.disk_state = DiskState::read(file_name, Some(hash));
             }
+        }
+    }
+
+    /// The buffer as it goes to disk, chunk by chunk, in the file's encoding
+    /// and with its BOM, line endings and missing final newline restored.
+    fn disk_chunks(&self) -> impl Iterator<Item = io::Result<Cow<'_, [u8]>>> {
+        let end = if self.missing_final_newline {
+            self.text.len_chars().saturating_sub(1)
         } else {
-            self.lines.len()
+            self.text.len_chars()
         };
-        for line in &mut self.lines[..until] {
-            start_comment = line.highlight(&self.file_type.highlights, word, start_comment);
-        }
+        let line_ending = self.line_ending;
+        let text = self.text.slice(..end);
+        let chunks: Box<dyn Iterator<Item = Cow<'_, str>>> = match &self.crlf_lines {
+            Some(crlf_lines) => Box::new(text.lines().zip(crlf_lines).map(|(line, is_crlf)| {
+                let line = line.to_string();
+                match line.strip_suffix('\n') {
+                    Some(content) if *is_crlf => Cow::Owned(format!("{content}\r\n")),
+                    _ => Cow::Owned(line),
+                }
+            })),
+            None => Box::new(text.chunks().map(move |chunk| match line_ending {
+                LineEnding::Lf => Cow::Borrowed(chunk),
+                LineEnding::CrLf => Cow::Owned(chunk.replace('\n', "\r\n")),
+            })),
+        };
+        let encoding = self.encoding;
+        let mut encoder = encoding.new_encoder();
+        let bom = self.bom.then_some(Ok(Cow::Borrowed(bom_bytes(encoding))));
+        bom.into_iter().chain(
+            chunks
+                .map(Some)
+                .chain(iter::once(None))
+                .map(move |chunk| encode_chunk(encoding, &mut encoder, chunk)),
+        )
     }
 
-    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
-        if at.y >= self.lines.len() {
+    /// Replaces the whole buffer with `text`, recovered from a swap file, as
+    /// one change that can be undone. Returns where the cursor should go.
+    /// A read-only buffer is left as it is.
+    pub fn recover(&mut self, text: &str) -> Position {
+        if self.read_only || !self.load() {
+            return Position::default();
+        }
+        let mut edits = vec![Edit::Delete {
+            at: Position::default(),
+            text: self.text.to_string(),
+        }];
+        if !text.is_empty() {
+            let mut text = text.to_string();
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            edits.push(Edit::Insert {
+                at: Position::default(),
+                text,
+            });
+        }
+        for edit in &edits {
+            self.apply(edit);
+        }
+        self.record(edits);
+        Position::default()
+    }
+    pub fn text(&self) -> String {
+        self.text.to_string()
+    }
+    /// How long until the swap file is due to be written, if the buffer has
+    /// changes it does not hold yet.
+    pub fn swap_wait(&self) -> Option<Duration> {
+        if !self.is_dirty || self.is_large() {
             return None;
         }
-        let mut position = Position { x: at.x, y: at.y };
-        let start = match direction {
-            SearchDirection::Forward => at.y,
-            SearchDirection::Backward => 0,
-        };
-        let end = match direction {
-            SearchDirection::Forward => self.lines.len(),
-            SearchDirection::Backward => at.y.saturating_add(1),
+        self.swap.as_ref()?.wait(self.changes)
+    }
+    /// Brings the swap file in line with the buffer: written while there are
+    /// unsaved changes, at most every `SWAP_INTERVAL`, and removed once
+    /// there are none. Failures are ignored, like for the undo history.
+    pub fn update_swap(&mut self) {
+        if self.is_large() {
+            return;
+        }
+        let Some(swap) = &mut self.swap else {
+            return;
         };
+        if !self.is_dirty {
+            swap.remove();
+        } else if swap.wait(self.changes).is_some_and(|wait| wait.is_zero()) {
+            let _ = swap.write(self.changes, self.text.chunks());
+        }
+    }
+    /// Called when the buffer is closed: keeps the undo history and drops
+    /// the swap file, whose changes are either saved or abandoned.
+    pub fn close(&mut self) {
+        self.persist_history();
+        if let Some(swap) = &mut self.swap {
+            swap.remove();
+        }
+    }
+
+    /// Writes the undo history to the cache so the next `open` of this file
+    /// can restore it. Failures are ignored, the history is a convenience.
+    pub fn persist_history(&self) {
+        if self.hex.is_some() {
+            return;
+        }
+        if let (Some(file_name), Some(content_hash)) = (&self.file_name, self.content_hash) {
+            let _ = self.history.persist(file_name, content_hash);
+        }
+    }
 
-        for _line in start..end {
-            if let Some(line) = self.lines.get(position.y) {
-                if let Some(x) = line.find(&query, position.x, direction) {
-                    position.x = x;
-                    return Some(position);
+    /// Highlights the lines in `rows`, reusing those highlighted before for
+    /// the same comment state and without search matches.
+    pub fn highlight(
+        &mut self,
+        word: &Option<Regex>,
+        search_options: &SearchOptions,
+        rows: Range<usize>,
+    ) {
+        if self.is_large() {
+            return;
+        }
+        let end = cmp::min(rows.end, self.len());
+        let mut start_with_comment = self.starts_in_comment(rows.start);
+        for y in rows.start..end {
+            let is_cached = word.is_none()
+                && self.lines.get(&y).is_some_and(|highlighted| {
+                    highlighted.start_with_comment == start_with_comment && !highlighted.has_matches
+                });
+            if !is_cached {
+                let Some(mut line) = self.line(y) else {
+                    break;
+                };
+                let end_in_comment = self.scan_comments(y..y + 1, start_with_comment);
+                line.highlight(
+                    &self.file_type.highlights,
+                    word,
+                    search_options,
+                    start_with_comment,
+                );
+                self.lines.insert(
+                    y,
+                    HighlightedLine {
+                        line,
+                        start_with_comment,
+                        end_in_comment,
+                        has_matches: word.is_some(),
+                    },
+                );
+            }
+            start_with_comment = self.lines[&y].end_in_comment;
+        }
+
+        if self.lines.len() > MAX_HIGHLIGHTED_LINES {
+            let keep = rows.start.saturating_sub(MAX_HIGHLIGHTED_LINES / 2)
+                ..end.saturating_add(MAX_HIGHLIGHTED_LINES / 2);
+            self.lines.retain(|y, _| keep.contains(y));
+        }
+    }
+    /// Whether line `y` starts inside a multiline comment, scanning from the
+    /// nearest checkpoint above it and adding the checkpoints passed on the
+    /// way.
+    fn starts_in_comment(&mut self, y: usize) -> bool {
+        if self.comment_checkpoints.is_empty() {
+            self.comment_checkpoints.push(false);
+        }
+        let checkpoint = y / COMMENT_CHECKPOINT_LINES;
+        while self.comment_checkpoints.len() <= checkpoint {
+            let last = self.comment_checkpoints.len() - 1;
+            let start = last * COMMENT_CHECKPOINT_LINES;
+            let inside = self.scan_comments(
+                start..start + COMMENT_CHECKPOINT_LINES,
+                self.comment_checkpoints[last],
+            );
+            self.comment_checkpoints.push(inside);
+        }
+        let start = checkpoint * COMMENT_CHECKPOINT_LINES;
+        self.scan_comments(start..y, self.comment_checkpoints[checkpoint])
+    }
+    /// Carries whether a multiline comment is open through `rows`.
+    fn scan_comments(&self, rows: Range<usize>, mut inside: bool) -> bool {
+        let end = cmp::min(rows.end, self.len());
+        if rows.start >= end {
+            return inside;
+        }
+        for line in self.text.lines_at(rows.start).take(end - rows.start) {
+            let line: Cow<'_, str> = line.into();
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            inside = Line::ends_in_comment(line, &self.file_type.highlights, inside);
+        }
+        inside
+    }
+
+    /// Finds the next match of `query` from `at`, moving line by line in
+    /// `options.direction`. Returns the match position and its grapheme length.
+    pub fn find(
+        &self,
+        query: &Regex,
+        at: &Position,
+        options: &SearchOptions,
+    ) -> Option<(Position, usize)> {
+        let mut position = Position { x: at.x, y: at.y };
+
+        loop {
+            let line = self.line(position.y)?;
+            if let Some((x, length)) = line.find(query, position.x, options) {
+                position.x = x;
+                return Some((position, length));
+            }
+            match options.direction {
+                SearchDirection::Forward if position.y + 1 < self.len() => {
+                    position.y += 1;
+                    position.x = 0;
                 }
-                match direction {
-                    SearchDirection::Forward => {
-                        position.y = position.y.saturating_add(1);
-                        position.x = 0;
-                    }
-                    SearchDirection::Backward => {
-                        position.y = position.y.saturating_sub(1);
-                        position.x = self.lines[position.y].length;
-                    }
+                SearchDirection::Backward if position.y > 0 => {
+                    position.y -= 1;
+                    position.x = self.line_length(position.y);
                 }
-            } else {
-                return None;
+                _ => return None,
             }
         }
-        None
+    }
+    /// Replaces the match of `regex` found at `at` and returns the grapheme
+    /// length of the inserted text. With `expand`, `$1`-style capture
+    /// references in `replacement` are substituted.
+    pub fn replace(
+        &mut self,
+        regex: &Regex,
+        at: &Position,
+        replacement: &str,
+        expand: bool,
+    ) -> Option<usize> {
+        if self.read_only || !self.load() {
+            return None;
+        }
+        let (edits, length) = self.apply_replacement(regex, at, replacement, expand)?;
+        self.record(edits);
+        Some(length)
+    }
+    /// Replaces every match of `regex` from `at` to the end of the buffer as
+    /// a single change, so one undo reverts them all. Returns how many were
+    /// replaced and where the last of them starts.
+    pub fn replace_all(
+        &mut self,
+        regex: &Regex,
+        at: &Position,
+        replacement: &str,
+        expand: bool,
+        options: &SearchOptions,
+    ) -> (usize, Option<Position>) {
+        if self.read_only || !self.load() {
+            return (0, None);
+        }
+        let mut at = *at;
+        let mut edits = Vec::new();
+        let mut replaced = 0;
+        let mut last = None;
+        while let Some((position, length)) = self.find(regex, &at, options) {
+            let Some((mut applied, inserted)) =
+                self.apply_replacement(regex, &position, replacement, expand)
+            else {
+                break;
+            };
+            edits.append(&mut applied);
+            replaced += 1;
+            last = Some(position);
+            at = Position {
+                x: position.x.saturating_add(inserted) + usize::from(length == 0),
+                y: position.y,
+            };
+        }
+        self.record(edits);
+        (replaced, last)
+    }
+    /// Replaces the match at `at` without recording it, returning the edits
+    /// made and the grapheme length of the inserted text.
+    fn apply_replacement(
+        &mut self,
+        regex: &Regex,
+        at: &Position,
+        replacement: &str,
+        expand: bool,
+    ) -> Option<(Vec<Edit>, usize)> {
+        let line = self.line(at.y)?;
+        let (removed, inserted) = line.match_replacement(regex, at.x, replacement, expand)?;
+        let length = inserted.graphemes(true).count();
+        let edits = vec![
+            Edit::Delete {
+                at: *at,
+                text: removed,
+            },
+            Edit::Insert {
+                at: *at,
+                text: inserted,
+            },
+        ];
+        for edit in &edits {
+            self.apply(edit);
+        }
+        Some((edits, length))
     }
     pub fn delete(&mut self, at: &Position) {
-        let len = self.lines.len();
-        if at.y >= len {
+        if self.read_only || !self.load() {
             return;
         }
-
-        self.is_dirty = true;
-        if at.x == self.lines[at.y].length && at.y + 1 < len {
-            let next_line = self.lines.remove(at.y + 1);
-            let line = &mut self.lines[at.y];
-            line.append(&next_line);
+        let len = self.len();
+        let Some(line) = self.line(at.y) else {
+            return;
+        };
+        let text = if at.x >= line.length {
+            if at.y + 1 >= len {
+                return;
+            }
+            String::from("\n")
+        } else if let Some(grapheme) = line.grapheme_at(at.x) {
+            grapheme.to_string()
         } else {
-            let line = &mut self.lines[at.y];
-            line.delete(at.x);
+            return;
+        };
+        let edit = Edit::Delete { at: *at, text };
+        self.apply(&edit);
+        self.record(vec![edit]);
+    }
+}
+
+/// Turns the line endings in `contents` into `\n` and makes sure it ends
+/// with one. Returns the ending for new lines, the more common one in mixed
+/// files, the ending of every line if they are mixed, and whether the final
+/// newline was missing.
+fn normalize_line_endings(contents: &mut String) -> (LineEnding, Option<Vec<bool>>, bool) {
+    let crlf_count = contents.matches("\r\n").count();
+    let lf_count = contents.matches('\n').count() - crlf_count;
+    let line_ending = if crlf_count > lf_count {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Lf
+    };
+    let mut crlf_lines = (crlf_count > 0 && lf_count > 0).then(|| {
+        contents
+            .split_inclusive('\n')
+            .filter(|line| line.ends_with('\n'))
+            .map(|line| line.ends_with("\r\n"))
+            .collect::<Vec<_>>()
+    });
+    if crlf_count > 0 {
+        *contents = contents.replace("\r\n", "\n");
+    }
+    let missing_final_newline = !contents.is_empty() && !contents.ends_with('\n');
+    if missing_final_newline {
+        contents.push('\n');
+        if let Some(crlf_lines) = &mut crlf_lines {
+            crlf_lines.push(false);
         }
-        self.unhighlight_lines(at.y);
+    }
+    (line_ending, crlf_lines, missing_final_newline)
+}
+
+/// Whether `file_name` may be opened for writing. A file that does not exist
+/// yet counts as writable, whether it can be created is up to the save.
+fn is_writable(file_name: &str) -> bool {
+    match fs::OpenOptions::new().append(true).open(file_name) {
+        Ok(_) => true,
+        Err(err) => err.kind() == ErrorKind::NotFound,
+    }
+}
+
+/// The encoding named by the BOM at the start of `bytes`, with the BOM's
+/// length. Without a BOM, text with a NUL in every other byte is taken as
+/// UTF-16, valid UTF-8 as UTF-8, and anything else is left to a guess from
+/// the byte frequencies.
+fn detect_encoding(bytes: &[u8]) -> (&'static Encoding, usize) {
+    if let Some(found) = Encoding::for_bom(bytes) {
+        return found;
+    }
+    if bytes.len().is_multiple_of(2) {
+        let pairs = bytes.len() / 2;
+        let zeros = |parity: usize| {
+            bytes
+                .iter()
+                .skip(parity)
+                .step_by(2)
+                .filter(|byte| **byte == 0)
+                .count()
+        };
+        let (even, odd) = (zeros(0), zeros(1));
+        if odd * 3 > pairs && even * 10 < odd {
+            return (UTF_16LE, 0);
+        }
+        if even * 3 > pairs && odd * 10 < even {
+            return (UTF_16BE, 0);
+        }
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return (UTF_8, 0);
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    (detector.guess(None, true), 0)
+}
+
+/// Whether `bytes` look like a binary file rather than text: a NUL byte or
+/// more than one control character in ten within the first 8 KiB.
+fn looks_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..cmp::min(bytes.len(), 8192)];
+    let controls = sample
+        .iter()
+        .filter(|byte| (**byte < 0x20 && !b"\t\n\r\x0c\x1b".contains(byte)) || **byte == 0x7f)
+        .count();
+    sample.contains(&0) || controls * 10 > sample.len()
+}
+
+fn bom_bytes(encoding: &'static Encoding) -> &'static [u8] {
+    if encoding == UTF_16LE {
+        b"\xff\xfe"
+    } else if encoding == UTF_16BE {
+        b"\xfe\xff"
+    } else {
+        b"\xef\xbb\xbf"
+    }
+}
+
+/// Encodes one chunk of the buffer as `encoding`. `None` marks the end of
+/// the text, so stateful encodings can close their last escape sequence.
+/// Characters the encoding cannot represent are an error rather than being
+/// replaced, so saving never loses text silently.
+fn encode_chunk<'a>(
+    encoding: &'static Encoding,
+    encoder: &mut Encoder,
+    chunk: Option<Cow<'a, str>>,
+) -> io::Result<Cow<'a, [u8]>> {
+    if encoding == UTF_8 {
+        return Ok(match chunk {
+            Some(Cow::Borrowed(chunk)) => Cow::Borrowed(chunk.as_bytes()),
+            Some(Cow::Owned(chunk)) => Cow::Owned(chunk.into_bytes()),
+            None => Cow::Borrowed(&[]),
+        });
+    }
+    let mut input = chunk.as_deref().unwrap_or("");
+    // encoding_rs only decodes UTF-16, so it is encoded here.
+    if encoding == UTF_16LE {
+        return Ok(Cow::Owned(
+            input.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        ));
+    }
+    if encoding == UTF_16BE {
+        return Ok(Cow::Owned(
+            input.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        ));
+    }
+    let mut output = Vec::with_capacity(input.len() + 16);
+    loop {
+        let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(
+            input,
+            &mut output,
+            chunk.is_none(),
+        );
+        input = &input[read..];
+        match result {
+            EncoderResult::InputEmpty => return Ok(Cow::Owned(output)),
+            EncoderResult::OutputFull => output.reserve(input.len() + 16),
+            EncoderResult::Unmappable(c) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("'{c}' cannot be saved as {}", encoding.name()),
+                ))
+            }
+        }
+    }
+}
+
+/// Follows `path` through any symlinks to the file they point at, so saving
+/// replaces the target and leaves the links in place. The target does not
+/// have to exist yet.
+fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(parent) => parent.join(target),
+                    None => target,
+                };
+            }
+            Ok(_) => return Ok(path),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(path),
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::other("too many levels of symbolic links"))
+}
+
+/// Writes `chunks` to a temporary file next to `path`, syncs it and renames it
+/// over `path`, so a crash or a full disk leaves either the old or the new
+/// contents on disk, never a truncated file. The original's permissions and,
+/// where allowed, its owner are carried over.
+fn write_atomically<'a>(
+    path: &Path,
+    chunks: impl Iterator<Item = io::Result<Cow<'a, [u8]>>>,
+) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "not a file name"))?
+        .to_string_lossy();
+    let original = match fs::metadata(path) {
+        Ok(metadata) => Some(metadata),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+
+    let mut attempt = 0;
+    let (temp_path, mut file) = loop {
+        let temp_path = directory.join(format!(".{name}.{}.{attempt}.tmp", process::id()));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => break (temp_path, file),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(err) => return Err(err),
+        }
+    };
+
+    let written = (|| {
+        for chunk in chunks {
+            file.write_all(&chunk?)?;
+        }
+        if let Some(original) = &original {
+            file.set_permissions(original.permissions())?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                // Only root may give a file away, so other users keep
+                // their own ownership of the rewritten file.
+                let _ =
+                    std::os::unix::fs::fchown(&file, Some(original.uid()), Some(original.gid()));
+            }
+        }
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if let Err(err) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    // Make the rename itself durable; not every platform can sync directories.
+    if let Ok(directory) = fs::File::open(directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
+
+/// Byte offset in `slice` of the grapheme boundary following `byte_index`,
+/// feeding the rope's chunks to the segmenter without copying the line.
+fn next_grapheme_boundary(slice: &RopeSlice, byte_index: usize) -> usize {
+    let (mut chunk, mut chunk_start, _, _) = slice.chunk_at_byte(byte_index);
+    let mut cursor = GraphemeCursor::new(byte_index, slice.len_bytes(), true);
+    loop {
+        match cursor.next_boundary(chunk, chunk_start) {
+            Ok(Some(boundary)) => return boundary,
+            Ok(None) => return slice.len_bytes(),
+            Err(GraphemeIncomplete::NextChunk) => {
+                chunk_start += chunk.len();
+                chunk = slice.chunk_at_byte(chunk_start).0;
+            }
+            Err(GraphemeIncomplete::PreContext(end)) => {
+                let context = slice.chunk_at_byte(end - 1).0;
+                cursor.provide_context(context, end - context.len());
+            }
+            Err(_) => return slice.len_bytes(),
+        }
+    }
+}
+
+/// Char offset of grapheme `at` in `slice` (clamped to its end), together
+/// with the number of graphemes walked. ASCII lines, where graphemes are
+/// chars, are answered from the rope's counts without walking the text.
+fn grapheme_to_char(slice: RopeSlice, at: usize) -> (usize, usize) {
+    let chars = slice.len_chars();
+    if slice.len_bytes() == chars {
+        let at = cmp::min(at, chars);
+        return (at, at);
+    }
+    let mut byte_index = 0;
+    let mut graphemes = 0;
+    while graphemes < at && byte_index < slice.len_bytes() {
+        byte_index = next_grapheme_boundary(&slice, byte_index);
+        graphemes += 1;
+    }
+    (slice.byte_to_char(byte_index), graphemes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bytes on disk after `bytes` are opened from a file named `name`,
+    /// changed by `edit` and saved.
+    fn round_trip(name: &str, bytes: &[u8], edit: impl FnOnce(&mut Document)) -> Vec<u8> {
+        round_trip_as(name, bytes, None, edit)
+    }
+
+    /// Like `round_trip`, with the file opened as `encoding`.
+    fn round_trip_as(
+        name: &str,
+        bytes: &[u8],
+        encoding: Option<&'static Encoding>,
+        edit: impl FnOnce(&mut Document),
+    ) -> Vec<u8> {
+        let path = history::tests::test_dir().join(name);
+        fs::write(&path, bytes).unwrap();
+        let mut document = Document::open_with_encoding(&path.to_string_lossy(), encoding).unwrap();
+        edit(&mut document);
+        document.save().unwrap();
+        fs::read(&path).unwrap()
+    }
+
+    fn rust_document(text: &str) -> Document {
+        let mut document = Document::from_bytes(text.as_bytes());
+        document.file_type = FileType::from("test.rs");
+        document
+    }
+
+    #[test]
+    fn highlighting_follows_comments_across_checkpoints() {
+        let inside = COMMENT_CHECKPOINT_LINES * 2;
+        let text = format!(
+            "/* open\n{}*/ closed\n{}",
+            "inside\n".repeat(inside),
+            "let x = 1;\n".repeat(COMMENT_CHECKPOINT_LINES * 2),
+        );
+        let mut document = rust_document(&text);
+        let options = SearchOptions::default();
+
+        document.highlight(&None, &options, inside - 5..inside + 5);
+        assert!(document.lines[&(inside - 5)].start_with_comment);
+        let after = inside + 10..inside + 20;
+        document.highlight(&None, &options, after.clone());
+        assert!(!document.lines[&after.start].start_with_comment);
+        // Only the rows drawn were highlighted.
+        assert_eq!(document.lines.len(), 20);
+
+        // Closing the comment on the first line frees everything below it.
+        document.insert(&Position { x: 7, y: 0 }, '*');
+        document.insert(&Position { x: 8, y: 0 }, '/');
+        document.highlight(&None, &options, inside - 5..inside + 5);
+        assert!(!document.lines[&(inside - 5)].start_with_comment);
+    }
+
+    #[test]
+    fn highlighting_keeps_only_rows_near_the_view() {
+        let mut document = rust_document(&"let x = 1;\n".repeat(MAX_HIGHLIGHTED_LINES * 4));
+        let options = SearchOptions::default();
+        for start in (0..MAX_HIGHLIGHTED_LINES * 4).step_by(50) {
+            document.highlight(&None, &options, start..start + 50);
+        }
+        assert!(document.lines.len() <= MAX_HIGHLIGHTED_LINES + 50);
+        assert!(document
+            .lines
+            .contains_key(&(MAX_HIGHLIGHTED_LINES * 4 - 1)));
+    }
+
+    #[test]
+    fn undo_empties_a_buffer_typed_into() {
+        let mut document = Document::default();
+        document.insert(&Position::default(), 'z');
+        document.insert(&Position { x: 1, y: 0 }, 'a');
+        assert_eq!(document.text(), "za\n");
+        assert!(document.is_dirty);
+
+        // Both characters are one step, and undoing it leaves no line behind.
+        document.undo();
+        assert_eq!(document.text(), "");
+        assert!(document.is_empty());
+        assert!(!document.is_dirty);
+        document.redo();
+        assert_eq!(document.text(), "za\n");
+
+        let mut document = Document::default();
+        document.insert(&Position::default(), '\n');
+        assert_eq!(document.len(), 1);
+        document.undo();
+        assert!(document.is_empty());
+    }
+
+    #[test]
+    fn read_only_buffer_refuses_every_change() {
+        let mut document = Document::from_bytes(b"one\ntwo\n");
+        document.insert(&Position::default(), 'x');
+        document.read_only = true;
+        let regex = Regex::new("o").unwrap();
+        let at = Position::default();
+
+        document.insert(&at, 'y');
+        document.delete(&at);
+        assert_eq!(
+            document.replace(&regex, &Position { x: 3, y: 0 }, "0", false),
+            None
+        );
+        let replaced = document.replace_all(&regex, &at, "0", false, &SearchOptions::default());
+        assert_eq!(replaced.0, 0);
+        document.set_line_ending(LineEnding::CrLf);
+        document.recover("recovered\n");
+        assert!(document.undo().is_none());
+        assert!(document.time_travel(60, true).is_none());
+        assert_eq!(document.text(), "xone\ntwo\n");
+        assert!(document.line_ending == LineEnding::Lf);
+    }
+
+    #[test]
+    fn crlf_file_saves_byte_for_byte() {
+        let bytes = b"one\r\ntwo\r\n";
+        assert_eq!(round_trip("crlf.txt", bytes, |_| ()), bytes);
+        let saved = round_trip("crlf.txt", bytes, |document| {
+            document.insert(&Position { x: 3, y: 0 }, '\n');
+        });
+        assert_eq!(saved, b"one\r\n\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn mixed_endings_stay_on_their_lines() {
+        let bytes = b"one\r\ntwo\nthree\r\n";
+        assert_eq!(round_trip("mixed.txt", bytes, |_| ()), bytes);
+        // A split line keeps its ending on its last part, the new line
+        // takes the file's usual ending.
+        let split = round_trip("mixed.txt", bytes, |document| {
+            document.insert(&Position { x: 1, y: 1 }, '\n');
+        });
+        assert_eq!(split, b"one\r\nt\r\nwo\nthree\r\n");
+        // Joined lines keep the ending of the last of them.
+        let joined = round_trip("mixed.txt", bytes, |document| {
+            document.delete(&Position { x: 3, y: 0 });
+        });
+        assert_eq!(joined, b"onetwo\nthree\r\n");
+    }
+
+    #[test]
+    fn missing_final_newline_stays_missing() {
+        let bytes = b"one\ntwo";
+        assert_eq!(round_trip("unterminated.txt", bytes, |_| ()), bytes);
+        let saved = round_trip("unterminated.txt", bytes, |document| {
+            document.insert(&Position { x: 3, y: 1 }, '!');
+        });
+        assert_eq!(saved, b"one\ntwo!");
+    }
+
+    #[test]
+    fn bom_is_kept() {
+        let bytes = b"\xef\xbb\xbfone\n";
+        assert_eq!(round_trip("bom.txt", bytes, |_| ()), bytes);
+        let saved = round_trip("bom.txt", bytes, |document| {
+            document.insert(&Position::default(), '>');
+        });
+        assert_eq!(saved, b"\xef\xbb\xbf>one\n");
+    }
+
+    #[test]
+    fn windows_1252_file_saves_in_its_encoding() {
+        let bytes = b"caf\xe9 \x80 5\n";
+        let windows_1252 = Some(encoding_rs::WINDOWS_1252);
+        assert_eq!(
+            round_trip_as("latin.txt", bytes, windows_1252, |_| ()),
+            bytes
+        );
+        let saved = round_trip_as("latin.txt", bytes, windows_1252, |document| {
+            assert_eq!(document.text(), "café € 5\n");
+            document.insert(&Position { x: 4, y: 0 }, 'è');
+        });
+        assert_eq!(saved, b"caf\xe9\xe8 \x80 5\n");
+    }
+
+    #[test]
+    fn utf_16_files_keep_their_byte_order_and_bom() {
+        let utf_16 = |text: &str, to_bytes: fn(u16) -> [u8; 2], bom: bool| {
+            let bom = bom.then_some('\u{feff}');
+            bom.into_iter()
+                .chain(text.chars())
+                .collect::<String>()
+                .encode_utf16()
+                .flat_map(to_bytes)
+                .collect::<Vec<u8>>()
+        };
+        for (name, to_bytes) in [
+            ("le.txt", u16::to_le_bytes as fn(u16) -> [u8; 2]),
+            ("be.txt", u16::to_be_bytes),
+        ] {
+            for bom in [false, true] {
+                let name = format!("{}{name}", if bom { "bom-" } else { "" });
+                let bytes = utf_16("één\n", to_bytes, bom);
+                assert_eq!(round_trip(&name, &bytes, |_| ()), bytes, "{name}");
+                let saved = round_trip(&name, &bytes, |document| {
+                    document.insert(&Position { x: 3, y: 0 }, '✓');
+                });
+                assert_eq!(saved, utf_16("één✓\n", to_bytes, bom), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn unmappable_character_fails_the_save() {
+        let bytes = b"caf\xe9\n";
+        let path = history::tests::test_dir().join("unmappable.txt");
+        fs::write(&path, bytes).unwrap();
+        let file_name = path.to_string_lossy();
+        let mut document =
+            Document::open_with_encoding(&file_name, Some(encoding_rs::WINDOWS_1252)).unwrap();
+        document.insert(&Position::default(), 'Ж');
+
+        let err = document.save().unwrap_err();
+        assert_eq!(err.to_string(), "'Ж' cannot be saved as windows-1252");
+        assert!(document.is_dirty);
+        // The file is left as it was, without a temporary file next to it.
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        let leftovers = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                name.starts_with(".unmappable.txt.") && name.ends_with(".tmp")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saving_through_symlinks_writes_their_target() {
+        use std::os::unix::fs::symlink;
+
+        let dir = history::tests::test_dir().join("symlinks");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("links")).unwrap();
+        let target = dir.join("target.txt");
+        fs::write(&target, "one\n").unwrap();
+        // A chain of relative links, the first from another directory.
+        let link = dir.join("links").join("link.txt");
+        symlink("../middle.txt", &link).unwrap();
+        symlink("target.txt", dir.join("middle.txt")).unwrap();
+
+        assert_eq!(
+            resolve_symlinks(&link).unwrap(),
+            dir.join("links/../target.txt")
+        );
+        let mut document = Document::open(&link.to_string_lossy()).unwrap();
+        document.insert(&Position::default(), '>');
+        document.save().unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b">one\n");
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert!(fs::symlink_metadata(dir.join("middle.txt"))
+            .unwrap()
+            .is_symlink());
+
+        // A dangling link gets its target created.
+        let dangling = dir.join("dangling.txt");
+        symlink("created.txt", &dangling).unwrap();
+        let path = resolve_symlinks(&dangling).unwrap();
+        write_atomically(&path, iter::once(Ok(Cow::Borrowed(&b"new\n"[..])))).unwrap();
+        assert_eq!(fs::read(dir.join("created.txt")).unwrap(), b"new\n");
+        assert!(fs::symlink_metadata(&dangling).unwrap().is_symlink());
     }
 }
@@ -1,30 +1,51 @@
-use crate::terminal::{Position, Terminal};
-use crossterm::event::Event;
-use crossterm::event::{read, Event::Key, KeyCode::Char, KeyEvent, KeyEventKind, KeyModifiers};
+use crate::arguments::{self, Arguments, FileArgument};
+use crate::documents::{Document, LineEnding};
+use crate::hex::{HexBuffer, NIBBLES_PER_ROW};
+use crate::large::INDEX_REFRESH;
+use crate::lines::Line;
+use crate::panel::Panel;
+use crate::swap;
+use crate::terminal::{Position, Size, Terminal};
+use crate::windows::{Area, Direction, Layout};
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use crossterm::style::Color;
-use crossterm::terminal::size;
-use std::fmt::format;
-use std::io::{self};
-use std::time::Instant;
-use std::{env, usize};
-///////////////////////////////////////////
-///need to handle execute. either execute or printing the string should be removed
-//////////////////////////////////////////
-/// have to handle the tilde printing. right now it skips one line when enter is pressed
-////////////////////////////////////////
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use std::{cmp, env, fs, iter, mem};
+use unicode_segmentation::UnicodeSegmentation;
+
 const NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const STATUS_FG_COLOR: Color = Color::Rgb {
+pub const STATUS_FG_COLOR: Color = Color::Rgb {
     r: 70,
     g: 70,
     b: 70,
 };
-const STATUS_BACKGROUND_COLOR: Color = Color::Rgb {
+pub const STATUS_BACKGROUND_COLOR: Color = Color::Rgb {
     r: 239,
     g: 239,
     b: 239,
 };
+/// Background of the status lines of the windows not being edited.
+pub const INACTIVE_STATUS_BACKGROUND_COLOR: Color = Color::Rgb {
+    r: 170,
+    g: 170,
+    b: 170,
+};
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
 pub const QUIT_N: u8 = 3;
+const QUIT_WARNING: &str = "WARNING!";
+/// Seconds without a keystroke after which a modified file is saved. Unset
+/// or 0 turns autosave off, both when idle and when the terminal loses focus.
+const AUTOSAVE_VARIABLE: &str = "GUARD_AUTOSAVE";
+const READ_ONLY_MESSAGE: &str =
+    "Read-only buffer: Alt-R allows editing, Alt-W saves over a protected file.";
+/// Percent of its split a window grows or shrinks by at a time.
+const RESIZE_STEP: isize = 5;
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum SearchDirection {
@@ -32,6 +53,69 @@ pub enum SearchDirection {
     Backward,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum CaseSensitivity {
+    Smart,
+    Sensitive,
+    Insensitive,
+}
+
+#[derive(Clone, Copy)]
+pub struct SearchOptions {
+    pub direction: SearchDirection,
+    pub case_sensitivity: CaseSensitivity,
+    pub whole_word: bool,
+    pub regex: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            direction: SearchDirection::Forward,
+            case_sensitivity: CaseSensitivity::Smart,
+            whole_word: false,
+            regex: false,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Compiles `query` into the pattern `Line::find` matches against. With
+    /// smart-case, the search only respects case when `query` has uppercase.
+    pub fn pattern(&self, query: &str) -> Result<Regex, regex::Error> {
+        let case_insensitive = match self.case_sensitivity {
+            CaseSensitivity::Sensitive => false,
+            CaseSensitivity::Insensitive => true,
+            CaseSensitivity::Smart => !query.chars().any(char::is_uppercase),
+        };
+        let pattern = if self.regex {
+            query.to_string()
+        } else {
+            regex::escape(query)
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+    }
+    pub fn flags(&self, query: &str) -> String {
+        let mut flags = String::from(match self.case_sensitivity {
+            CaseSensitivity::Smart => "smart case",
+            CaseSensitivity::Sensitive => "match case",
+            CaseSensitivity::Insensitive => "ignore case",
+        });
+        if self.whole_word {
+            flags.push_str(", word");
+        }
+        if self.regex {
+            flags.push_str(", regex");
+            if Regex::new(query).is_err() {
+                flags.push_str(" (invalid)");
+            }
+        }
+        format!("[{flags}] ")
+    }
+}
+
 pub struct Message {
     text: String,
     time: Instant,
@@ -48,129 +132,1889 @@ impl From<String> for Message {
 
 pub struct Editor {
     should_quit: bool,
-    terminal: Terminal,
     cursor_position: Position,
     offset: Position,
-    //document: Document
+    preferred_x: Option<usize>,
+    document: Document,
     status_message: Message,
     quit_times: u8,
-    highlighted_word: Option<String>,
+    highlighted_word: Option<Regex>,
+    search_options: SearchOptions,
+    prompt_prefix: String,
+    prompt_cursor: Option<usize>,
+    prompt_history: HashMap<String, Vec<String>>,
+    panel: Option<Panel>,
+    autosave: Option<Duration>,
+    last_key: Instant,
+    /// Every open buffer. The one at `current` is being edited and left
+    /// empty here, its state lives in the fields above until another buffer
+    /// is switched to.
+    buffers: Vec<Buffer>,
+    current: usize,
+    read_only: bool,
+    /// Every window on screen. The one at `window` is being edited and shows
+    /// buffer `current`, its viewport lives in the fields above until another
+    /// window is switched to.
+    windows: Vec<Window>,
+    window: usize,
+    layout: Layout,
+}
+
+/// An open document and where its view was left.
+#[derive(Default)]
+struct Buffer {
+    document: Document,
+    cursor_position: Position,
+    offset: Position,
+    preferred_x: Option<usize>,
+}
+
+/// A view onto one of the buffers. Windows on the same buffer share its
+/// document, so an edit in one shows up in all of them.
+#[derive(Clone, Copy, Default)]
+struct Window {
+    buffer: usize,
+    cursor_position: Position,
+    offset: Position,
+    preferred_x: Option<usize>,
 }
 
 impl Default for Editor {
     fn default() -> Self {
-        let _args: Vec<String> = env::args().collect();
-        let initial_stat = String::from("HELP: Ctrl-f -> Find | Ctrl-s ->Save | Ctrl-q -> Quit");
+        Self::new(Arguments::default())
+    }
+}
 
-        Editor {
+impl Editor {
+    pub fn new(arguments: Arguments) -> Self {
+        let mut editor = Editor {
             should_quit: false,
-            terminal: Terminal::default(),
             cursor_position: Position::default(),
             offset: Position::default(),
-            status_message: Message::from(initial_stat),
+            preferred_x: None,
+            document: Document::default(),
+            status_message: Message::from(String::from(
+                "HELP: Ctrl-f -> Find | Ctrl-g -> Go to line | Ctrl-z/y -> Undo/Redo | Ctrl-s ->Save | Ctrl-q -> Quit",
+            )),
             quit_times: QUIT_N,
             highlighted_word: None,
+            search_options: SearchOptions::default(),
+            prompt_prefix: String::new(),
+            prompt_cursor: None,
+            prompt_history: HashMap::new(),
+            panel: None,
+            autosave: env::var(AUTOSAVE_VARIABLE)
+                .ok()
+                .and_then(|seconds| seconds.trim().parse::<u64>().ok())
+                .filter(|seconds| *seconds > 0)
+                .map(Duration::from_secs),
+            last_key: Instant::now(),
+            buffers: Vec::new(),
+            current: 0,
+            read_only: arguments.read_only,
+            windows: vec![Window::default()],
+            window: 0,
+            layout: Layout::Window(0),
+        };
+        let mut error = None;
+        for argument in arguments.files {
+            let (buffer, message) = editor.load(argument);
+            error = error.or(message);
+            editor.buffers.push(buffer);
+        }
+        if editor.buffers.is_empty() {
+            editor.buffers.push(Buffer::default());
         }
+        editor.activate(0);
+        if let Some(error) = error {
+            editor.status_message = Message::from(error);
+        } else if editor.buffers.len() > 1 {
+            editor.status_message = Message::from(format!(
+                "Opened {} buffers: Alt-N/Alt-P switch, Alt-B lists them.",
+                editor.buffers.len()
+            ));
+        }
+        editor
     }
-}
+    /// Opens a file from the command line or the open prompt as a buffer,
+    /// with the cursor where it asked. Also returns a message when the file
+    /// could not be read or mixes line endings.
+    fn load(&self, argument: FileArgument) -> (Buffer, Option<String>) {
+        let (mut document, error) = match &argument.file_name {
+            Some(file_name) => match Document::open(file_name) {
+                Ok(document) => (document, None),
+                Err(err) if err.kind() == ErrorKind::NotFound => (Document::new(file_name), None),
+                Err(err) => (
+                    Document::default(),
+                    Some(format!("ERR: Could not open file {file_name}: {err}")),
+                ),
+            },
+            None => match Self::read_stdin() {
+                Ok(document) => (document, None),
+                Err(err) => (
+                    Document::default(),
+                    Some(format!("ERR: Could not read standard input: {err}")),
+                ),
+            },
+        };
+        document.read_only |= self.read_only;
+        let error = error.or_else(|| {
+            document.crlf_lines.is_some().then(|| {
+                String::from("The file mixes LF and CRLF endings, each line keeps its own. Ctrl-T makes them all one.")
+            })
+        });
 
-impl Editor {
+        let y = argument
+            .line
+            .map_or(0, |line| cmp::min(line.saturating_sub(1), document.len()));
+        let x = argument.column.map_or(0, |column| {
+            cmp::min(column.saturating_sub(1), document.line_length(y))
+        });
+        let buffer = Buffer {
+            document,
+            cursor_position: Position { x, y },
+            ..Buffer::default()
+        };
+        (buffer, error)
+    }
+    /// Reads a buffer piped in on standard input, then points standard
+    /// input back at the terminal for the keys.
+    fn read_stdin() -> Result<Document, io::Error> {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        Terminal::reattach_stdin()?;
+        Ok(Document::from_bytes(&bytes))
+    }
     pub fn refresh_screen(&mut self) -> Result<(), io::Error> {
         Terminal::hide_cursor()?;
+        Terminal::move_cursor(Position::default())?;
 
-        if self.should_quit == true {
+        if self.should_quit {
             Terminal::clear_screen()?;
-
             Terminal::print("Bye for now!")?;
         } else {
-            self.draw_rows()?;
-            Terminal::move_cursor(Position { x: 0, y: 0 })?;
-            Terminal::print(&self.content)?;
-            Terminal::move_cursor(self.position)?;
+            let mut areas = self.window_areas()?;
+            for (index, area) in &areas {
+                let window = self.window_state(*index);
+                let document = if window.buffer == self.current {
+                    &mut self.document
+                } else {
+                    &mut self.buffers[window.buffer].document
+                };
+                document.highlight(
+                    &self.highlighted_word,
+                    &self.search_options,
+                    window.offset.y..window.offset.y.saturating_add(area.height),
+                );
+            }
+            let active = areas
+                .iter()
+                .find(|(index, _)| *index == self.window)
+                .map_or(Area::default(), |(_, area)| *area);
+
+            let screen = Self::screen_size()?;
+            if let Some(panel) = &self.panel {
+                panel.draw(screen)?;
+                let status = Area {
+                    x: 0,
+                    y: screen.height,
+                    width: screen.width,
+                    height: 1,
+                };
+                self.draw_status_bar(&self.active_window(), status, true)?;
+            } else {
+                // Each row is cleared to its end before it is drawn, so the
+                // windows further right go after the ones they sit beside.
+                areas.sort_by_key(|(_, area)| area.x);
+                for (index, area) in areas {
+                    let window = self.window_state(index);
+                    self.draw_rows(&window, area)?;
+                    let status = Area {
+                        y: area.y + area.height.saturating_sub(1),
+                        height: 1,
+                        ..area
+                    };
+                    self.draw_status_bar(&window, status, index == self.window)?;
+                }
+            }
+            self.draw_message_bar()?;
+            if let Some(panel) = &self.panel {
+                Terminal::move_cursor(Position {
+                    x: 0,
+                    y: panel.selected + 1 - panel.offset(screen.height),
+                })?;
+            } else if let Some(prompt_cursor) = self.prompt_cursor {
+                let size = Terminal::size()?;
+                Terminal::move_cursor(Position {
+                    x: cmp::min(prompt_cursor, size.width.saturating_sub(1)),
+                    y: size.height.saturating_sub(1),
+                })?;
+            } else if self.document.hex.is_some() {
+                Terminal::move_cursor(Position {
+                    x: active.x + HexBuffer::column(self.cursor_position.x),
+                    y: active.y + self.cursor_position.y.saturating_sub(self.offset.y),
+                })?;
+            } else {
+                Terminal::move_cursor(Position {
+                    x: active.x + self.cursor_position.x.saturating_sub(self.offset.x),
+                    y: active.y + self.cursor_position.y.saturating_sub(self.offset.y),
+                })?;
+            }
         }
         Terminal::show_cursor()?;
         Terminal::execute()?;
         Ok(())
     }
-    pub fn evaluate_event(&mut self, event: &Event) {
-        if let Key(KeyEvent {
+    pub fn evaluate_event(&mut self, event: &Event) -> Result<(), io::Error> {
+        match event {
+            Event::FocusLost => self.autosave("on focus loss"),
+            Event::FocusGained => {
+                self.check_disk()?;
+            }
+            _ => (),
+        }
+        if let Event::Key(KeyEvent {
             code,
             modifiers,
-            kind,
-            state,
+            kind: KeyEventKind::Press | KeyEventKind::Repeat,
+            ..
         }) = event
         {
-            //println!(
-            //    "Code: {code:#?}, Modifier: {modifiers:#?}, Kind: {kind:#?}, State: {state:#?}"
-            //);
-            match code {
-                Char('q') if *modifiers == KeyModifiers::CONTROL => {
+            self.last_key = Instant::now();
+            match (code, *modifiers) {
+                (KeyCode::Char('q'), KeyModifiers::CONTROL)
+                    if self.quit_times > 0
+                        && self.documents().any(|document| document.is_dirty) =>
+                {
+                    let dirty = self
+                        .documents()
+                        .filter(|document| document.is_dirty)
+                        .count();
+                    self.status_message = Message::from(if dirty == 1 && self.document.is_dirty {
+                        format!(
+                            "{QUIT_WARNING} File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+                            self.quit_times
+                        )
+                    } else {
+                        format!(
+                            "{QUIT_WARNING} {dirty} buffer(s) have unsaved changes. Press Ctrl-Q {} more times to quit.",
+                            self.quit_times
+                        )
+                    });
+                    self.quit_times -= 1;
+                    return Ok(());
+                }
+                (KeyCode::Char('q'), KeyModifiers::CONTROL) => {
+                    for document in self.documents_mut() {
+                        document.close();
+                    }
                     self.should_quit = true;
                 }
-                Char(c) if *kind == KeyEventKind::Release => {
-                    self.content.push(*c);
-                    self.position.x += 1;
+                (KeyCode::Char('w'), KeyModifiers::CONTROL) => self.close_buffer()?,
+                (KeyCode::Char('o'), KeyModifiers::CONTROL) => self.open_file()?,
+                (KeyCode::Char('n'), KeyModifiers::ALT) => {
+                    self.switch_buffer((self.current + 1) % self.buffers.len())?;
+                }
+                (KeyCode::Char('p'), KeyModifiers::ALT) => {
+                    let index = (self.current + self.buffers.len() - 1) % self.buffers.len();
+                    self.switch_buffer(index)?;
+                }
+                (KeyCode::Char('b'), KeyModifiers::ALT) => self.pick_buffer()?,
+                (KeyCode::Char('h'), KeyModifiers::ALT) => {
+                    self.split_window(Direction::Horizontal)?;
+                }
+                (KeyCode::Char('v'), KeyModifiers::ALT) => {
+                    self.split_window(Direction::Vertical)?
+                }
+                (KeyCode::Char('o'), KeyModifiers::ALT) => self.cycle_window(1)?,
+                (KeyCode::Char('i'), KeyModifiers::ALT) => self.cycle_window(-1)?,
+                (KeyCode::Char('x'), KeyModifiers::ALT) => self.close_window()?,
+                (KeyCode::Char('='), KeyModifiers::ALT) => self.resize_window(RESIZE_STEP)?,
+                (KeyCode::Char('-'), KeyModifiers::ALT) => self.resize_window(-RESIZE_STEP)?,
+                (KeyCode::Char('s'), KeyModifiers::CONTROL) => self.save()?,
+                (KeyCode::Char('s'), KeyModifiers::ALT) => self.save_as()?,
+                (KeyCode::Char('e'), KeyModifiers::CONTROL) => self.change_encoding()?,
+                (KeyCode::Char('w'), KeyModifiers::ALT) => self.force_save()?,
+                (KeyCode::Char('r'), KeyModifiers::ALT) => self.toggle_read_only(),
+                _ if self.document.read_only && Self::is_edit(*code, *modifiers) => {
+                    self.status_message = Message::from(String::from(READ_ONLY_MESSAGE));
+                }
+                _ if self.document.hex.is_some() => self.evaluate_hex_key(*code, *modifiers)?,
+                (KeyCode::Char('g'), KeyModifiers::CONTROL) => self.goto_line()?,
+                (KeyCode::Char('f'), KeyModifiers::CONTROL) => self.search()?,
+                (KeyCode::Char('r'), KeyModifiers::CONTROL) => self.replace()?,
+                (KeyCode::Char('z'), KeyModifiers::CONTROL) => {
+                    if let Some(position) = self.document.undo() {
+                        self.cursor_position = position;
+                        self.preferred_x = None;
+                    }
+                }
+                (KeyCode::Char('y'), KeyModifiers::CONTROL) => {
+                    if let Some(position) = self.document.redo() {
+                        self.cursor_position = position;
+                        self.preferred_x = None;
+                    }
+                }
+                (KeyCode::Left, KeyModifiers::ALT) => {
+                    let position = self.document.switch_branch(false);
+                    self.restore_history_position(position, "No other branch to switch to.");
+                }
+                (KeyCode::Right, KeyModifiers::ALT) => {
+                    let position = self.document.switch_branch(true);
+                    self.restore_history_position(position, "No other branch to switch to.");
+                }
+                (KeyCode::Char('e'), KeyModifiers::ALT) => self.time_travel(true)?,
+                (KeyCode::Char('l'), KeyModifiers::ALT) => self.time_travel(false)?,
+                (KeyCode::Char('u'), KeyModifiers::ALT) => self.undo_tree()?,
+                (KeyCode::Char('t'), KeyModifiers::CONTROL) => self.toggle_line_ending(),
+                (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                    self.document.insert(&self.cursor_position, *c);
+                    self.move_cursor(KeyCode::Right, KeyModifiers::NONE)?;
+                }
+                (KeyCode::Tab, _) => {
+                    self.document.insert(&self.cursor_position, '\t');
+                    self.move_cursor(KeyCode::Right, KeyModifiers::NONE)?;
+                }
+                (KeyCode::Enter, _) => {
+                    self.document.insert(&self.cursor_position, '\n');
+                    self.preferred_x = None;
+                    self.cursor_position = Position {
+                        x: 0,
+                        y: self.cursor_position.y.saturating_add(1),
+                    };
                 }
-                crossterm::event::KeyCode::Enter => {
-                    self.content.push('\n');
-                    self.position.y += 1;
-                    self.position.x = 0;
+                (KeyCode::Backspace, _)
+                    if self.cursor_position.x > 0 || self.cursor_position.y > 0 =>
+                {
+                    self.move_cursor(KeyCode::Left, KeyModifiers::NONE)?;
+                    self.document.delete(&self.cursor_position);
                 }
+                (KeyCode::Delete, _) => self.document.delete(&self.cursor_position),
+                (
+                    KeyCode::Up
+                    | KeyCode::Down
+                    | KeyCode::Left
+                    | KeyCode::Right
+                    | KeyCode::Home
+                    | KeyCode::End
+                    | KeyCode::PageUp
+                    | KeyCode::PageDown,
+                    _,
+                ) => self.move_cursor(*code, *modifiers)?,
                 _ => (),
             }
+            if self.quit_times < QUIT_N {
+                self.quit_times = QUIT_N;
+                // The key may have left a message of its own to keep.
+                if self.status_message.text.starts_with(QUIT_WARNING) {
+                    self.status_message = Message::from(String::new());
+                }
+            }
+        }
+        self.scroll()
+    }
+    /// Keys in the hex view: hex digits overwrite the nibble under the
+    /// cursor, the movement keys walk nibbles and rows.
+    fn evaluate_hex_key(
+        &mut self,
+        code: KeyCode,
+        modifiers: KeyModifiers,
+    ) -> Result<(), io::Error> {
+        match (code, modifiers) {
+            (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => match c.to_digit(16) {
+                Some(value) if self.document.set_nibble(&self.cursor_position, value as u8) => {
+                    self.move_hex_cursor(KeyCode::Right, KeyModifiers::NONE)?;
+                }
+                Some(_) => (),
+                None => {
+                    self.status_message =
+                        Message::from(String::from("Type 0-9 or a-f to change a byte."));
+                }
+            },
+            (
+                KeyCode::Up
+                | KeyCode::Down
+                | KeyCode::Left
+                | KeyCode::Right
+                | KeyCode::Home
+                | KeyCode::End
+                | KeyCode::PageUp
+                | KeyCode::PageDown,
+                _,
+            ) => self.move_hex_cursor(code, modifiers)?,
+            _ => (),
         }
+        Ok(())
+    }
+    fn move_hex_cursor(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<(), io::Error> {
+        let page = self.view_size()?.height * NIBBLES_PER_ROW;
+        let last = self
+            .document
+            .hex
+            .as_ref()
+            .map_or(0, |hex| (hex.bytes.len() * 2).saturating_sub(1));
+        let Position { x, y } = self.cursor_position;
+        let nibble = y * NIBBLES_PER_ROW + x;
+        let row_start = nibble - x;
+
+        let nibble = match (key, modifiers) {
+            (KeyCode::Home, KeyModifiers::CONTROL) => 0,
+            (KeyCode::End, KeyModifiers::CONTROL) => last,
+            (KeyCode::Home, _) => row_start,
+            (KeyCode::End, _) => row_start + NIBBLES_PER_ROW - 1,
+            (KeyCode::Left, _) => nibble.saturating_sub(1),
+            (KeyCode::Right, _) => nibble + 1,
+            (KeyCode::Up, _) => nibble.checked_sub(NIBBLES_PER_ROW).unwrap_or(nibble),
+            (KeyCode::Down, _) => nibble + NIBBLES_PER_ROW,
+            (KeyCode::PageUp, _) => nibble.saturating_sub(page),
+            (KeyCode::PageDown, _) => nibble + page,
+            _ => nibble,
+        };
+        let nibble = cmp::min(nibble, last);
+        self.cursor_position = Position {
+            x: nibble % NIBBLES_PER_ROW,
+            y: nibble / NIBBLES_PER_ROW,
+        };
+        Ok(())
     }
     pub fn repl(&mut self) -> Result<(), io::Error> {
+        self.scroll()?;
+        self.offer_recovery()?;
         loop {
             self.refresh_screen()?;
             if self.should_quit {
                 break;
             }
 
-            let event = read()?;
-            self.evaluate_event(&event);
+            // Wake up for whichever comes first, the message expiring, the
+            // swap file or the autosave falling due or more lines indexed,
+            // or wait for a key if none of them is pending.
+            let message_timeout =
+                Some(MESSAGE_TIMEOUT.saturating_sub(self.status_message.time.elapsed()))
+                    .filter(|timeout| !timeout.is_zero());
+            let indexing = self
+                .document
+                .large
+                .as_ref()
+                .and_then(|large| large.indexing_progress())
+                .map(|_| INDEX_REFRESH);
+            let timeout = message_timeout
+                .into_iter()
+                .chain(self.documents().filter_map(Document::swap_wait))
+                .chain(self.autosave_wait())
+                .chain(indexing)
+                .min();
+            let is_ready = match timeout {
+                Some(timeout) => poll(timeout)?,
+                None => true,
+            };
+            if is_ready {
+                let event = read()?;
+                self.evaluate_event(&event)?;
+            }
+            // The documents are closed on quitting, and their unsaved changes
+            // abandoned, so nothing may write them out again.
+            if self.should_quit {
+                continue;
+            }
+            if self.autosave_wait().is_some_and(|wait| wait.is_zero()) {
+                self.autosave("after being idle");
+            }
+            for document in self.documents_mut() {
+                document.update_swap();
+            }
+        }
+
+        Ok(())
+    }
+    /// How long until the idle autosave is due, if it is on and a buffer
+    /// has changes it may save.
+    fn autosave_wait(&self) -> Option<Duration> {
+        let delay = self.autosave?;
+        self.documents()
+            .any(Self::is_autosaved)
+            .then(|| delay.saturating_sub(self.last_key.elapsed()))
+    }
+    /// Whether the autosave looks after `document`: it has unsaved changes
+    /// and a file it may write them to.
+    fn is_autosaved(document: &Document) -> bool {
+        document.is_dirty && !document.read_only && document.file_name.is_some()
+    }
+    fn autosave(&mut self, reason: &str) {
+        if self.autosave.is_none() || !self.documents().any(Self::is_autosaved) {
+            return;
+        }
+        // A failed save is retried after the next idle period, not at once.
+        self.last_key = Instant::now();
+        let mut saved: usize = 0;
+        let mut changed: usize = 0;
+        let mut error = None;
+        for document in self.documents_mut() {
+            if !Self::is_autosaved(document) {
+                continue;
+            }
+            // Nobody may be around to answer, so a file changed on disk is
+            // left for the next manual save to sort out.
+            if document.changed_on_disk() {
+                changed += 1;
+                continue;
+            }
+            match document.save() {
+                Ok(()) => saved += 1,
+                Err(err) => error = Some(err),
+            }
+        }
+        self.status_message = Message::from(match (error, changed, saved) {
+            (Some(err), _, _) => format!("Autosave failed: {err}"),
+            (None, 1, _) => String::from("Not autosaved: the file changed on disk."),
+            (None, 2.., _) => format!("Not autosaved: {changed} files changed on disk."),
+            (None, 0, 1) => format!("Autosaved {reason}."),
+            (None, 0, _) => format!("Autosaved {saved} buffers {reason}."),
+        });
+    }
+    /// Offers what to do with the swap file an editor left behind when it
+    /// died with unsaved changes to this file.
+    fn offer_recovery(&mut self) -> Result<(), io::Error> {
+        let Some(swap) = &mut self.document.swap else {
+            return Ok(());
+        };
+        if let Some(pid) = swap.owner {
+            self.status_message = Message::from(format!(
+                "Process {pid} is editing this file too; its swap file is left alone."
+            ));
+            return Ok(());
+        }
+        let Some(leftover) = swap.leftover.take() else {
+            return Ok(());
+        };
+
+        let question = format!(
+            "Unsaved changes from process {}: (r)ecover, (d)iff, (x) delete, (k)eep",
+            leftover.pid
+        );
+        loop {
+            match self.read_choice(&question)? {
+                // The swap file stays for a session that may edit the file.
+                Some('r') if self.document.read_only => {
+                    if let Some(swap) = &mut self.document.swap {
+                        swap.owner = Some(leftover.pid);
+                    }
+                    self.status_message = Message::from(String::from(
+                        "Read-only buffer: changes not recovered, the swap file is kept.",
+                    ));
+                }
+                Some('r') => {
+                    self.cursor_position = self.document.recover(&leftover.text);
+                    self.status_message =
+                        Message::from(String::from("Changes recovered, save to keep them."));
+                }
+                Some('d') => {
+                    let lines = swap::diff(&self.document.text(), &leftover.text);
+                    let title = "Unsaved changes (Esc to close; - on disk, + in swap file)";
+                    self.pick(title, lines, 0)?;
+                    continue;
+                }
+                Some('x') => {
+                    if let Some(swap) = &mut self.document.swap {
+                        swap.discard();
+                    }
+                    self.status_message = Message::from(String::from("Swap file deleted."));
+                }
+                _ => {
+                    // Leave the file for a later session and write no swap
+                    // file of our own over it.
+                    if let Some(swap) = &mut self.document.swap {
+                        swap.owner = Some(leftover.pid);
+                    }
+                    self.status_message = Message::from(String::from(
+                        "Swap file kept, this session's changes are not swapped.",
+                    ));
+                }
+            }
+            return Ok(());
+        }
+    }
+    /// Looks for changes another program made to the file and reports
+    /// whether the buffer may still be saved over it. A clean buffer is
+    /// reloaded right away, a dirty one asks first.
+    fn check_disk(&mut self) -> Result<bool, io::Error> {
+        if !self.document.changed_on_disk() {
+            return Ok(true);
+        }
+        if !self.document.is_dirty {
+            self.reload("File changed on disk, reloaded.");
+            return Ok(false);
+        }
+        loop {
+            match self.read_choice("File changed on disk: (r)eload, (k)eep yours, (d)iff")? {
+                Some('r') => {
+                    self.reload("Reloaded, unsaved changes dropped.");
+                    return Ok(false);
+                }
+                Some('k') => {
+                    self.document.keep_over_disk();
+                    self.status_message =
+                        Message::from(String::from("Kept yours, saving overwrites the file."));
+                    return Ok(true);
+                }
+                Some('d') => {
+                    let Some(file_name) = self.document.file_name.clone() else {
+                        return Ok(true);
+                    };
+                    let on_disk = match Document::open_with_encoding(&file_name, self.encoding()) {
+                        Ok(document) => document,
+                        Err(err) => {
+                            self.status_message =
+                                Message::from(format!("Could not read the file: {err}"));
+                            return Ok(false);
+                        }
+                    };
+                    let lines =
+                        swap::diff(&Self::diff_text(&self.document), &Self::diff_text(&on_disk));
+                    let title = "Changes on disk (Esc to close; - in buffer, + on disk)";
+                    self.pick(title, lines, 0)?;
+                }
+                _ => {
+                    self.status_message =
+                        Message::from(String::from("File changed on disk, left as is."));
+                    return Ok(false);
+                }
+            }
+        }
+    }
+    /// The encoding to read the file with again: the buffer's own, or a
+    /// fresh guess for binary and large files.
+    fn encoding(&self) -> Option<&'static Encoding> {
+        (self.document.hex.is_none() && self.document.large.is_none())
+            .then_some(self.document.encoding)
+    }
+    /// The buffer as lines to compare, binary files as their hex rows.
+    fn diff_text(document: &Document) -> String {
+        match &document.hex {
+            Some(hex) => (0..hex.rows())
+                .filter_map(|row| hex.render(row, usize::MAX))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            None => document.text(),
+        }
+    }
+    fn reload(&mut self, message: &str) {
+        let Some(file_name) = self.document.file_name.clone() else {
+            return;
+        };
+        let encoding = self.encoding();
+        self.document.close();
+        match Document::open_with_encoding(&file_name, encoding) {
+            Ok(document) => {
+                self.replace_document(document);
+                self.status_message = Message::from(message.to_string());
+            }
+            Err(err) => {
+                self.status_message = Message::from(format!("Could not reload: {err}"));
+            }
+        }
+    }
+    /// Swaps in `document`, keeping the cursor where it was if it still fits
+    /// and the buffer read-only if it was.
+    fn replace_document(&mut self, mut document: Document) {
+        document.read_only = self.document.read_only;
+        self.document = document;
+        let y = cmp::min(self.cursor_position.y, self.document.len());
+        let x = cmp::min(self.cursor_position.x, self.document.line_length(y));
+        self.cursor_position = Position { x, y };
+        self.preferred_x = None;
+    }
+    /// Every open document, the one being edited first.
+    fn documents(&self) -> impl Iterator<Item = &Document> {
+        let current = self.current;
+        iter::once(&self.document).chain(
+            self.buffers
+                .iter()
+                .enumerate()
+                .filter(move |(index, _)| *index != current)
+                .map(|(_, buffer)| &buffer.document),
+        )
+    }
+    fn documents_mut(&mut self) -> impl Iterator<Item = &mut Document> {
+        let current = self.current;
+        iter::once(&mut self.document).chain(
+            self.buffers
+                .iter_mut()
+                .enumerate()
+                .filter(move |(index, _)| *index != current)
+                .map(|(_, buffer)| &mut buffer.document),
+        )
+    }
+    /// Puts the state of the buffer being edited back into its slot.
+    fn stash(&mut self) {
+        self.buffers[self.current] = Buffer {
+            document: mem::take(&mut self.document),
+            cursor_position: self.cursor_position,
+            offset: self.offset,
+            preferred_x: self.preferred_x,
+        };
+    }
+    /// Starts editing buffer `index`, taking its state out of its slot.
+    fn activate(&mut self, index: usize) {
+        let buffer = mem::take(&mut self.buffers[index]);
+        self.document = buffer.document;
+        self.cursor_position = buffer.cursor_position;
+        self.offset = buffer.offset;
+        self.preferred_x = buffer.preferred_x;
+        self.current = index;
+    }
+    /// Switches to buffer `index` and catches up on what happened to its file
+    /// meanwhile.
+    fn switch_buffer(&mut self, index: usize) -> Result<(), io::Error> {
+        if index == self.current {
+            self.status_message = Message::from(String::from("No other buffer."));
+            return Ok(());
+        }
+        self.stash();
+        self.activate(index);
+        self.status_message = Message::from(format!(
+            "Buffer {}/{}: {}",
+            index + 1,
+            self.buffers.len(),
+            self.document.file_name.as_deref().unwrap_or("[No Name]")
+        ));
+        self.scroll()?;
+        self.offer_recovery()?;
+        self.check_disk()?;
+        Ok(())
+    }
+    fn pick_buffer(&mut self) -> Result<(), io::Error> {
+        let items = (0..self.buffers.len())
+            .map(|index| {
+                let document = if index == self.current {
+                    &self.document
+                } else {
+                    &self.buffers[index].document
+                };
+                format!(
+                    "{}{}",
+                    document.file_name.as_deref().unwrap_or("[No Name]"),
+                    if document.is_dirty { " (modified)" } else { "" }
+                )
+            })
+            .collect();
+        let title = "Buffers (Enter to switch, Esc to close)";
+        if let Some(index) = self.pick(title, items, self.current)? {
+            if index != self.current {
+                self.switch_buffer(index)?;
+            }
+        }
+        Ok(())
+    }
+    /// Opens a file, `FILE[:LINE[:COLUMN]]`, in a buffer of its own, or
+    /// switches to it if it is open already. An empty unnamed buffer is
+    /// replaced instead of kept.
+    fn open_file(&mut self) -> Result<(), io::Error> {
+        let input = self.prompt("Open: ", |_, _, _| {})?;
+        let Some(input) = input.filter(|input| !input.trim().is_empty()) else {
+            return Ok(());
+        };
+        let argument = arguments::file_argument(input.trim());
+        let open = (0..self.buffers.len()).find(|index| {
+            let document = if *index == self.current {
+                &self.document
+            } else {
+                &self.buffers[*index].document
+            };
+            document.file_name.is_some() && document.file_name == argument.file_name
+        });
+        if let Some(index) = open {
+            if index != self.current {
+                self.switch_buffer(index)?;
+            }
+            return Ok(());
         }
 
+        let (buffer, error) = self.load(argument);
+        let is_unused = self.document.file_name.is_none()
+            && !self.document.is_dirty
+            && self.document.is_empty();
+        self.stash();
+        let index = if is_unused {
+            self.buffers[self.current] = buffer;
+            self.current
+        } else {
+            self.buffers.push(buffer);
+            self.buffers.len() - 1
+        };
+        self.activate(index);
+        self.status_message = Message::from(
+            error.unwrap_or_else(|| format!("Buffer {}/{} opened.", index + 1, self.buffers.len())),
+        );
+        self.scroll()?;
+        self.offer_recovery()?;
+        Ok(())
+    }
+    /// Closes the buffer being edited, asking first when it has unsaved
+    /// changes. Closing the last one leaves an empty buffer.
+    fn close_buffer(&mut self) -> Result<(), io::Error> {
+        if self.document.is_dirty {
+            let question = format!(
+                "{} has unsaved changes: (s)ave, (d)iscard, (c)ancel",
+                self.document.file_name.as_deref().unwrap_or("[No Name]")
+            );
+            match self.read_choice(&question)? {
+                Some('s') => {
+                    self.save()?;
+                    if self.document.is_dirty {
+                        return Ok(());
+                    }
+                }
+                Some('d') => (),
+                _ => {
+                    self.status_message = Message::from(String::from("Buffer kept open."));
+                    return Ok(());
+                }
+            }
+        }
+        self.document.close();
+        let removed = self.current;
+        self.buffers.remove(removed);
+        if self.buffers.is_empty() {
+            self.buffers.push(Buffer::default());
+        }
+        let index = cmp::min(removed, self.buffers.len() - 1);
+        // Other windows on the closed buffer move to the one that replaces it.
+        for window in &mut self.windows {
+            if window.buffer == removed {
+                *window = Window {
+                    buffer: index,
+                    ..Window::default()
+                };
+            } else if window.buffer > removed {
+                window.buffer -= 1;
+            }
+        }
+        self.activate(index);
+        self.status_message = Message::from(String::from("Buffer closed."));
+        self.scroll()?;
+        self.offer_recovery()?;
+        self.check_disk()?;
         Ok(())
     }
+    /// The document of buffer `index`, wherever it lives.
+    fn buffer_document(&self, index: usize) -> &Document {
+        if index == self.current {
+            &self.document
+        } else {
+            &self.buffers[index].document
+        }
+    }
+    /// The window being edited as it is now.
+    fn active_window(&self) -> Window {
+        Window {
+            buffer: self.current,
+            cursor_position: self.cursor_position,
+            offset: self.offset,
+            preferred_x: self.preferred_x,
+        }
+    }
+    fn window_state(&self, index: usize) -> Window {
+        if index == self.window {
+            self.active_window()
+        } else {
+            self.windows[index]
+        }
+    }
+    /// Splits the window being edited in two, both on its buffer, and moves
+    /// to the new one.
+    fn split_window(&mut self, direction: Direction) -> Result<(), io::Error> {
+        let size = self.view_size()?;
+        let has_room = match direction {
+            Direction::Horizontal => size.height >= 3,
+            Direction::Vertical => size.width >= 3,
+        };
+        if !has_room {
+            self.status_message = Message::from(String::from("Not enough room to split."));
+            return Ok(());
+        }
+        let new = self.windows.len();
+        self.windows.push(self.active_window());
+        self.layout.split(self.window, new, direction);
+        self.focus_window(new)?;
+        self.status_message = Message::from(String::from(
+            "Alt-O/Alt-I move between windows, Alt-=/Alt-- resize, Alt-X closes.",
+        ));
+        Ok(())
+    }
+    /// Moves to window `index`, leaving the one being edited as it is.
+    fn focus_window(&mut self, index: usize) -> Result<(), io::Error> {
+        self.windows[self.window] = self.active_window();
+        self.enter_window(index)
+    }
+    /// Starts editing window `index` and its buffer.
+    fn enter_window(&mut self, index: usize) -> Result<(), io::Error> {
+        let window = self.windows[index];
+        let switches_buffer = window.buffer != self.current;
+        if switches_buffer {
+            self.stash();
+            self.activate(window.buffer);
+        }
+        self.window = index;
+        self.offset = window.offset;
+        self.preferred_x = window.preferred_x;
+        // Edits made through another window may have moved the end of the
+        // buffer from under this one's cursor.
+        let y = cmp::min(window.cursor_position.y, self.document.len());
+        let x = if self.document.hex.is_some() {
+            window.cursor_position.x
+        } else {
+            cmp::min(window.cursor_position.x, self.document.line_length(y))
+        };
+        self.cursor_position = Position { x, y };
+        self.scroll()?;
+        if switches_buffer {
+            self.offer_recovery()?;
+        }
+        self.check_disk()?;
+        Ok(())
+    }
+    /// Moves `by` windows forward, or backward when negative, in the order
+    /// they were opened.
+    fn cycle_window(&mut self, by: isize) -> Result<(), io::Error> {
+        if self.windows.len() == 1 {
+            self.status_message = Message::from(String::from("No other window."));
+            return Ok(());
+        }
+        let index = self
+            .window
+            .checked_add_signed(by)
+            .unwrap_or(self.windows.len() - 1)
+            % self.windows.len();
+        self.focus_window(index)
+    }
+    /// Closes the window being edited, its buffer stays open.
+    fn close_window(&mut self) -> Result<(), io::Error> {
+        if self.windows.len() == 1 {
+            self.status_message = Message::from(String::from("The last window stays open."));
+            return Ok(());
+        }
+        self.layout.remove(self.window);
+        self.windows.remove(self.window);
+        let index = cmp::min(self.window, self.windows.len() - 1);
+        self.enter_window(index)?;
+        self.status_message = Message::from(String::from("Window closed."));
+        Ok(())
+    }
+    fn resize_window(&mut self, by: isize) -> Result<(), io::Error> {
+        if !self.layout.resize(self.window, by) {
+            self.status_message = Message::from(String::from("No split to resize."));
+        }
+        self.scroll()
+    }
     pub fn run(&mut self) {
         Terminal::initialize().unwrap();
         let result = self.repl();
         Terminal::terminate().unwrap();
         result.unwrap();
     }
-    pub fn welcome_msg() -> Result<(), io::Error> {
-        let (t_x, _t_y) = size()?;
-        let version_msg = &format!("{NAME} Editor -- version {VERSION}");
-        let v_msg_width = version_msg.len();
-        let v_msg_padding = (t_x - v_msg_width as u16) / 2;
-        let v_spaces = " ".repeat(v_msg_padding as usize - 1);
-        let v_msg_string = &format!("~{v_spaces}{version_msg}\n");
+    /// Reads a line of input in the message bar. `callback` runs after every
+    /// keystroke that edits or moves inside the input; Enter confirms and Esc
+    /// cancels. Ctrl-P / Ctrl-N browse the history kept for this `label`, and
+    /// callbacks may set `prompt_prefix` to show state in front of the label.
+    pub fn prompt<C>(&mut self, label: &str, mut callback: C) -> Result<Option<String>, io::Error>
+    where
+        C: FnMut(&mut Self, KeyEvent, &str),
+    {
+        let history = self.prompt_history.get(label).cloned().unwrap_or_default();
+        let mut history_index: Option<usize> = None;
+        let mut draft = String::new();
+        let mut input = Line::default();
+        let mut cursor = 0;
+
+        let result = loop {
+            let label = format!("{}{label}", self.prompt_prefix);
+            self.status_message = Message::from(format!("{label}{}", input.string));
+            self.prompt_cursor = Some(label.graphemes(true).count().saturating_add(cursor));
+            self.refresh_screen()?;
+
+            let Event::Key(key) = read()? else {
+                continue;
+            };
+            if !matches!(key.kind, KeyEventKind::Press | KeyEventKind::Repeat) {
+                continue;
+            }
+            match (key.code, key.modifiers) {
+                (KeyCode::Enter, _) => break Some(input.string),
+                (KeyCode::Esc, _) => break None,
+                (KeyCode::Backspace, _) if cursor > 0 => {
+                    cursor -= 1;
+                    input.delete(cursor);
+                }
+                (KeyCode::Delete, _) => input.delete(cursor),
+                (KeyCode::Left, _) => cursor = cursor.saturating_sub(1),
+                (KeyCode::Right, _) => cursor = cmp::min(cursor.saturating_add(1), input.length),
+                (KeyCode::Home, _) | (KeyCode::Char('a'), KeyModifiers::CONTROL) => cursor = 0,
+                (KeyCode::End, _) | (KeyCode::Char('e'), KeyModifiers::CONTROL) => {
+                    cursor = input.length;
+                }
+                (KeyCode::Char('p'), KeyModifiers::CONTROL) if !history.is_empty() => {
+                    let index = match history_index {
+                        Some(index) => index.saturating_sub(1),
+                        None => {
+                            draft = input.string.clone();
+                            history.len() - 1
+                        }
+                    };
+                    history_index = Some(index);
+                    input = Line::from(history[index].as_str());
+                    cursor = input.length;
+                }
+                (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
+                    if let Some(index) = history_index {
+                        history_index = Some(index + 1).filter(|index| *index < history.len());
+                        input = match history_index {
+                            Some(index) => Line::from(history[index].as_str()),
+                            None => Line::from(draft.as_str()),
+                        };
+                        cursor = input.length;
+                    }
+                }
+                (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                    input.insert(cursor, c);
+                    cursor += 1;
+                }
+                _ => (),
+            }
+            callback(self, key, &input.string);
+        };
 
-        let width = "Stop Talking, Code Instead!".len();
-        let padding = (t_x - width as u16) / 2;
-        let spaces = " ".repeat(padding as usize - 1);
-        let welcome_msg = &format!("~{spaces}Stop Talking, Start Coding!");
-        Terminal::print(v_msg_string)?;
-        Terminal::print(welcome_msg)?;
+        self.prompt_cursor = None;
+        self.prompt_prefix.clear();
+        self.status_message = Message::from(String::new());
+        if let Some(input) = result.as_ref().filter(|input| !input.is_empty()) {
+            let history = self.prompt_history.entry(label.to_string()).or_default();
+            if history.last() != Some(input) {
+                history.push(input.clone());
+            }
+        }
+        Ok(result)
+    }
+    fn search_pattern(&self, query: &str) -> Option<Regex> {
+        if query.is_empty() {
+            return None;
+        }
+        self.search_options.pattern(query).ok()
+    }
+    /// Handles the Alt-C (case), Alt-W (whole word) and Alt-R (regex) toggles
+    /// shared by the search and replace prompts.
+    fn toggle_search_option(&mut self, key: KeyEvent) -> bool {
+        let options = &mut self.search_options;
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('c'), KeyModifiers::ALT) => {
+                options.case_sensitivity = match options.case_sensitivity {
+                    CaseSensitivity::Smart => CaseSensitivity::Sensitive,
+                    CaseSensitivity::Sensitive => CaseSensitivity::Insensitive,
+                    CaseSensitivity::Insensitive => CaseSensitivity::Smart,
+                };
+            }
+            (KeyCode::Char('w'), KeyModifiers::ALT) => options.whole_word = !options.whole_word,
+            (KeyCode::Char('r'), KeyModifiers::ALT) => options.regex = !options.regex,
+            _ => return false,
+        }
+        true
+    }
+    /// Searches from `at` and wraps around the buffer when the end (or the
+    /// start, going backward) is reached.
+    fn find_wrapping(&self, regex: &Regex, at: &Position) -> Option<(Position, usize)> {
+        let options = &self.search_options;
+        self.document.find(regex, at, options).or_else(|| {
+            let wrap_at = match options.direction {
+                SearchDirection::Forward => Position::default(),
+                SearchDirection::Backward => {
+                    let y = self.document.len().saturating_sub(1);
+                    let x = self.document.line_length(y);
+                    Position { x, y }
+                }
+            };
+            self.document.find(regex, &wrap_at, options)
+        })
+    }
+    fn search(&mut self) -> Result<(), io::Error> {
+        let old_position = self.cursor_position;
+        let old_offset = self.offset;
+        self.prompt_prefix = self.search_options.flags("");
+
+        let query = self.prompt(
+            "Search (ESC to cancel, Up/Down to navigate, Alt-C/W/R case/word/regex): ",
+            |editor, key, query| {
+                let mut at = editor.cursor_position;
+                editor.search_options.direction = match key.code {
+                    KeyCode::Down => {
+                        at.x = at.x.saturating_add(1);
+                        SearchDirection::Forward
+                    }
+                    KeyCode::Up => SearchDirection::Backward,
+                    _ => {
+                        editor.toggle_search_option(key);
+                        SearchDirection::Forward
+                    }
+                };
+                editor.prompt_prefix = editor.search_options.flags(query);
+                editor.highlighted_word = editor.search_pattern(query);
+
+                if let Some(regex) = &editor.highlighted_word {
+                    if let Some((position, _)) = editor.find_wrapping(regex, &at) {
+                        editor.cursor_position = position;
+                        let _ = editor.scroll();
+                    }
+                }
+            },
+        )?;
+
+        if query.is_none() {
+            self.cursor_position = old_position;
+            self.offset = old_offset;
+        }
+        self.search_options.direction = SearchDirection::Forward;
+        self.preferred_x = None;
+        self.clear_highlighted_word();
         Ok(())
     }
-    pub fn draw_rows(&self) -> Result<(), io::Error> {
-        let terminal_szie = size()?;
-        let content_lines = self.content.matches('\n').count() + 1;
+    /// Stops highlighting the search matches. The rows that showed them are
+    /// highlighted again on the next refresh, in every window.
+    fn clear_highlighted_word(&mut self) {
+        self.highlighted_word = None;
+    }
+    fn replace(&mut self) -> Result<(), io::Error> {
+        self.prompt_prefix = self.search_options.flags("");
+        let query = self.prompt(
+            "Replace (Alt-C/W/R case/word/regex): ",
+            |editor, key, query| {
+                editor.toggle_search_option(key);
+                editor.prompt_prefix = editor.search_options.flags(query);
+            },
+        )?;
+        let Some(query) = query.filter(|query| !query.is_empty()) else {
+            return Ok(());
+        };
+        let Some(regex) = self.search_pattern(&query) else {
+            self.status_message = Message::from(format!("Invalid regular expression: {query}"));
+            return Ok(());
+        };
+        let Some(replacement) = self.prompt("Replace with: ", |_, _, _| {})? else {
+            return Ok(());
+        };
 
-        for current_row in 0..terminal_szie.1 {
-            Terminal::clear_cline()?;
+        self.highlighted_word = Some(regex.clone());
+        self.search_options.direction = SearchDirection::Forward;
+        let expand = self.search_options.regex;
+        let mut at = self.cursor_position;
+        let mut replaced = 0;
 
-            if current_row == terminal_szie.1 / 3 && content_lines <= 1 {
-                Self::welcome_msg()?;
-            } else if current_row as usize >= content_lines {
-                Terminal::print("~")?;
+        while let Some((position, length)) = self.document.find(&regex, &at, &self.search_options) {
+            self.cursor_position = position;
+            self.scroll()?;
+            let skip = Position {
+                x: position.x.saturating_add(cmp::max(length, 1)),
+                y: position.y,
+            };
+            match self.read_choice("Replace this match? (y)es (n)o (a)ll (q)uit")? {
+                Some('y') => (),
+                Some('a') => {
+                    let (count, last) = self.document.replace_all(
+                        &regex,
+                        &position,
+                        &replacement,
+                        expand,
+                        &self.search_options,
+                    );
+                    replaced += count;
+                    self.cursor_position = last.unwrap_or(position);
+                    break;
+                }
+                Some('n') => {
+                    at = skip;
+                    continue;
+                }
+                _ => break,
             }
-            if current_row + 1 < terminal_szie.1 {
-                Terminal::print("\r\n")?;
+            let Some(inserted) = self
+                .document
+                .replace(&regex, &position, &replacement, expand)
+            else {
+                break;
+            };
+            replaced += 1;
+            at = Position {
+                x: position.x.saturating_add(inserted) + usize::from(length == 0),
+                y: position.y,
+            };
+        }
+
+        self.preferred_x = None;
+        self.clear_highlighted_word();
+        self.status_message = Message::from(format!("Replaced {replaced} occurrence(s)."));
+        Ok(())
+    }
+    fn read_choice(&mut self, question: &str) -> Result<Option<char>, io::Error> {
+        self.status_message = Message::from(question.to_string());
+        self.refresh_screen()?;
+        loop {
+            if let Event::Key(KeyEvent {
+                code,
+                kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                ..
+            }) = read()?
+            {
+                return Ok(match code {
+                    KeyCode::Char(c) => Some(c.to_ascii_lowercase()),
+                    _ => None,
+                });
+            }
+        }
+    }
+    /// Shows `items` in a panel over the text area and returns the index
+    /// picked with Enter, or `None` when the panel is closed with Esc.
+    pub fn pick(
+        &mut self,
+        title: &str,
+        items: Vec<String>,
+        selected: usize,
+    ) -> Result<Option<usize>, io::Error> {
+        if items.is_empty() {
+            return Ok(None);
+        }
+        let selected = cmp::min(selected, items.len() - 1);
+        self.panel = Some(Panel {
+            title: title.to_string(),
+            items,
+            selected,
+        });
+
+        let result = loop {
+            self.refresh_screen()?;
+            let page = Self::screen_size()?.height.saturating_sub(1);
+            let Event::Key(key) = read()? else {
+                continue;
+            };
+            if !matches!(key.kind, KeyEventKind::Press | KeyEventKind::Repeat) {
+                continue;
+            }
+            let Some(panel) = &mut self.panel else {
+                break None;
+            };
+            match key.code {
+                KeyCode::Enter => break Some(panel.selected),
+                KeyCode::Esc | KeyCode::Char('q') => break None,
+                code => panel.move_selection(code, page),
+            }
+        };
+        self.panel = None;
+        Ok(result)
+    }
+    fn restore_history_position(&mut self, position: Option<Position>, otherwise: &str) {
+        if let Some(position) = position {
+            self.cursor_position = position;
+            self.preferred_x = None;
+        } else {
+            self.status_message = Message::from(otherwise.to_string());
+        }
+    }
+    fn time_travel(&mut self, earlier: bool) -> Result<(), io::Error> {
+        let label = if earlier {
+            "Go back in time by seconds: "
+        } else {
+            "Go forward in time by seconds: "
+        };
+        let input = self.prompt(label, |_, _, _| {})?;
+        let Some(input) = input.filter(|input| !input.is_empty()) else {
+            return Ok(());
+        };
+        // The direction comes from the key, so a sign is not accepted.
+        let Ok(seconds) = input.trim().parse::<u64>() else {
+            self.status_message = Message::from(format!("Invalid number of seconds: {input}"));
+            return Ok(());
+        };
+
+        let position = self.document.time_travel(seconds, earlier);
+        let otherwise = if earlier {
+            "Already at the earliest change."
+        } else {
+            "Already at the latest change."
+        };
+        self.restore_history_position(position, otherwise);
+        Ok(())
+    }
+    fn undo_tree(&mut self) -> Result<(), io::Error> {
+        let entries = self.document.history.entries();
+        let now = SystemTime::now();
+        let items = entries
+            .iter()
+            .map(|entry| {
+                let current = if entry.is_current { '>' } else { ' ' };
+                let saved = if entry.is_saved { '*' } else { ' ' };
+                let age = format_age(now.duration_since(entry.time).unwrap_or_default());
+                let summary = if entry.id == 0 {
+                    "(original)"
+                } else {
+                    entry.summary.as_str()
+                };
+                format!(
+                    "{current}{saved} {}#{} {age:>8}  {summary}",
+                    "  ".repeat(entry.depth),
+                    entry.id
+                )
+            })
+            .collect();
+        let selected = entries
+            .iter()
+            .position(|entry| entry.is_current)
+            .unwrap_or(0);
+
+        let title = "Undo tree (Enter to restore, Esc to close; > current, * saved)";
+        if let Some(index) = self.pick(title, items, selected)? {
+            let position = self.document.travel_to(entries[index].id);
+            self.restore_history_position(position, "Already at that change.");
+        }
+        Ok(())
+    }
+    fn goto_line(&mut self) -> Result<(), io::Error> {
+        let input = self.prompt("Go to line[:column]: ", |_, _, _| {})?;
+        let Some(input) = input.filter(|input| !input.is_empty()) else {
+            return Ok(());
+        };
+        let mut parts = input.trim().splitn(2, ':');
+        let line = parts.next().and_then(|line| line.parse::<usize>().ok());
+        let column = parts.next().map(|column| column.parse::<usize>().ok());
+
+        match (line, column) {
+            (Some(line), None | Some(Some(_))) => {
+                let y = cmp::min(line.saturating_sub(1), self.document.len());
+                let length = self.document.line_length(y);
+                let x = cmp::min(column.flatten().unwrap_or(1).saturating_sub(1), length);
+                self.cursor_position = Position { x, y };
+                self.preferred_x = None;
+                self.scroll()?;
+            }
+            _ => {
+                self.status_message = Message::from(format!("Invalid line number: {input}"));
             }
         }
         Ok(())
     }
+    fn toggle_line_ending(&mut self) {
+        let line_ending = match self.document.line_ending {
+            LineEnding::Lf => LineEnding::CrLf,
+            LineEnding::CrLf => LineEnding::Lf,
+        };
+        self.document.set_line_ending(line_ending);
+        self.status_message = Message::from(format!(
+            "Line endings will be saved as {}.",
+            line_ending.name()
+        ));
+    }
+    fn change_encoding(&mut self) -> Result<(), io::Error> {
+        let encodings = [
+            UTF_8,
+            UTF_16LE,
+            UTF_16BE,
+            encoding_rs::WINDOWS_1252,
+            encoding_rs::ISO_8859_15,
+            encoding_rs::ISO_8859_2,
+            encoding_rs::WINDOWS_1250,
+            encoding_rs::WINDOWS_1251,
+            encoding_rs::KOI8_R,
+            encoding_rs::SHIFT_JIS,
+            encoding_rs::EUC_JP,
+            encoding_rs::ISO_2022_JP,
+            encoding_rs::GBK,
+            encoding_rs::GB18030,
+            encoding_rs::BIG5,
+            encoding_rs::EUC_KR,
+        ];
+        let current = self.document.encoding;
+        let items = encodings
+            .iter()
+            .map(|encoding| {
+                let marker = if *encoding == current { '>' } else { ' ' };
+                format!("{marker} {}", encoding.name())
+            })
+            .collect();
+        let selected = encodings
+            .iter()
+            .position(|encoding| *encoding == current)
+            .unwrap_or(0);
+
+        let title = "Encoding (Enter to choose, Esc to close; > current)";
+        let Some(index) = self.pick(title, items, selected)? else {
+            return Ok(());
+        };
+        let encoding = encodings[index];
+        // A binary file has no text to save, only a way back to one.
+        if self.document.hex.is_some() {
+            self.reopen_with_encoding(encoding);
+            return Ok(());
+        }
+        let name = encoding.name();
+        let question =
+            format!("Reopen as {name}, dropping unsaved changes (r), or save as {name} (s)?");
+        match self.read_choice(&question)? {
+            Some('r') => self.reopen_with_encoding(encoding),
+            Some('s') => self.save_with_encoding(encoding)?,
+            _ => self.status_message = Message::from(String::from("Encoding unchanged.")),
+        }
+        Ok(())
+    }
+    fn reopen_with_encoding(&mut self, encoding: &'static Encoding) {
+        let Some(file_name) = self.document.file_name.clone() else {
+            self.status_message = Message::from(String::from("No file to reopen."));
+            return;
+        };
+        // Closing first keeps our own swap file from looking left over.
+        self.document.close();
+        match Document::open_with_encoding(&file_name, Some(encoding)) {
+            Ok(document) => {
+                self.replace_document(document);
+                self.status_message = Message::from(format!("Reopened as {}.", encoding.name()));
+            }
+            Err(err) => {
+                self.status_message =
+                    Message::from(format!("Could not reopen as {}: {err}", encoding.name()));
+            }
+        }
+    }
+    fn save_with_encoding(&mut self, encoding: &'static Encoding) -> Result<(), io::Error> {
+        if !self.check_disk()? {
+            return Ok(());
+        }
+        let previous = (self.document.encoding, self.document.bom);
+        // UTF-16 files are written with a BOM so other tools can tell the
+        // byte order, legacy encodings cannot carry one.
+        self.document.bom = if encoding == UTF_8 {
+            self.document.bom
+        } else {
+            encoding == UTF_16LE || encoding == UTF_16BE
+        };
+        self.document.encoding = encoding;
+        self.status_message = match self.document.save() {
+            Ok(()) => Message::from(format!("Saved as {}.", encoding.name())),
+            Err(err) => {
+                (self.document.encoding, self.document.bom) = previous;
+                Message::from(format!("Could not save as {}: {err}", encoding.name()))
+            }
+        };
+        Ok(())
+    }
+    fn save(&mut self) -> Result<(), io::Error> {
+        if self.document.file_name.is_none() {
+            return self.save_as();
+        }
+        if self.document.read_only {
+            self.status_message = Message::from(String::from(READ_ONLY_MESSAGE));
+            return Ok(());
+        }
+        if !self.check_disk()? {
+            return Ok(());
+        }
+        self.status_message = match self.document.save() {
+            Ok(()) => Message::from(String::from("File saved successfully.")),
+            Err(err) => Message::from(format!("Error writing file: {err}")),
+        };
+        Ok(())
+    }
+    /// Saves the buffer even if it is read-only, replacing a write-protected
+    /// file. When its directory is not writable either, offers to save it
+    /// through sudo, which gets the terminal to ask for a password.
+    fn force_save(&mut self) -> Result<(), io::Error> {
+        if self.document.file_name.is_none() {
+            return self.save_as();
+        }
+        if !self.check_disk()? {
+            return Ok(());
+        }
+        let err = match self.document.force_save() {
+            Ok(()) => {
+                self.status_message = Message::from(String::from("File saved successfully."));
+                return Ok(());
+            }
+            Err(err) => err,
+        };
+        let is_denied = err
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == ErrorKind::PermissionDenied);
+        if !is_denied {
+            self.status_message = Message::from(format!("Error writing file: {err}"));
+            return Ok(());
+        }
+        if self.read_choice("Permission denied in the file's directory. Save with sudo? (y/n)")?
+            != Some('y')
+        {
+            self.status_message = Message::from(String::from("Save aborted."));
+            return Ok(());
+        }
+
+        Terminal::clear_screen()?;
+        Terminal::move_cursor(Position::default())?;
+        Terminal::terminate()?;
+        let result = self.document.save_with_sudo();
+        Terminal::initialize()?;
+        self.status_message = match result {
+            Ok(()) => Message::from(String::from("File saved with sudo.")),
+            Err(err) => Message::from(format!("Error writing file: {err}")),
+        };
+        Ok(())
+    }
+    fn toggle_read_only(&mut self) {
+        self.document.read_only = !self.document.read_only;
+        self.status_message = Message::from(String::from(if self.document.read_only {
+            "Buffer is read-only."
+        } else {
+            "Editing allowed."
+        }));
+    }
+    /// Whether `code` changes the buffer, which a read-only buffer refuses.
+    fn is_edit(code: KeyCode, modifiers: KeyModifiers) -> bool {
+        matches!(
+            (code, modifiers),
+            (KeyCode::Char(_), KeyModifiers::NONE | KeyModifiers::SHIFT)
+                | (
+                    KeyCode::Tab | KeyCode::Enter | KeyCode::Backspace | KeyCode::Delete,
+                    _
+                )
+                | (KeyCode::Char('r' | 'z' | 'y' | 't'), KeyModifiers::CONTROL)
+                | (KeyCode::Left | KeyCode::Right, KeyModifiers::ALT)
+                | (KeyCode::Char('e' | 'l' | 'u'), KeyModifiers::ALT)
+        )
+    }
+    /// Asks for a file name and saves the buffer under it from then on.
+    /// Overwriting another file and creating missing directories are
+    /// confirmed first.
+    fn save_as(&mut self) -> Result<(), io::Error> {
+        let input = self.prompt("Save as: ", |_, _, _| {})?;
+        let Some(file_name) = input
+            .map(|input| input.trim().to_string())
+            .filter(|input| !input.is_empty())
+        else {
+            self.status_message = Message::from(String::from("Save aborted."));
+            return Ok(());
+        };
+        let path = Path::new(&file_name);
+        if path.exists() && self.document.file_name.as_deref() != Some(file_name.as_str()) {
+            let question = format!("{file_name} exists, overwrite it? (y/n)");
+            if self.read_choice(&question)? != Some('y') {
+                self.status_message = Message::from(String::from("Save aborted."));
+                return Ok(());
+            }
+        }
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty() && !parent.exists())
+        {
+            let question = format!("Create directory {}? (y/n)", parent.display());
+            if self.read_choice(&question)? != Some('y') {
+                self.status_message = Message::from(String::from("Save aborted."));
+                return Ok(());
+            }
+            if let Err(err) = fs::create_dir_all(parent) {
+                self.status_message = Message::from(format!("Could not create directory: {err}"));
+                return Ok(());
+            }
+        }
+        self.status_message = match self.document.save_as(&file_name) {
+            Ok(()) => Message::from(format!("Saved as {file_name}.")),
+            Err(err) => Message::from(format!("Error writing file: {err}")),
+        };
+        Ok(())
+    }
+    fn move_cursor(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<(), io::Error> {
+        let terminal_height = self.view_size()?.height;
+        let Position { mut x, mut y } = self.cursor_position;
+        let height = self.document.len();
+        let line_length = |y: usize| self.document.line_length(y);
+
+        match (key, modifiers) {
+            (KeyCode::Up, _) => y = y.saturating_sub(1),
+            (KeyCode::Down, _) => y = cmp::min(y.saturating_add(1), height),
+            (KeyCode::PageUp, _) => y = y.saturating_sub(terminal_height),
+            (KeyCode::PageDown, _) => y = cmp::min(y.saturating_add(terminal_height), height),
+            (KeyCode::Home, KeyModifiers::CONTROL) => {
+                x = 0;
+                y = 0;
+            }
+            (KeyCode::End, KeyModifiers::CONTROL) => {
+                y = height.saturating_sub(1);
+                x = line_length(y);
+            }
+            (KeyCode::Home, _) => x = 0,
+            (KeyCode::End, _) => x = line_length(y),
+            (KeyCode::Left, KeyModifiers::CONTROL) => {
+                if x > 0 {
+                    x = self
+                        .document
+                        .line(y)
+                        .map_or(0, |line| line.previous_word_start(x));
+                } else if y > 0 {
+                    y -= 1;
+                    x = line_length(y);
+                }
+            }
+            (KeyCode::Right, KeyModifiers::CONTROL) => {
+                if x < line_length(y) {
+                    x = self
+                        .document
+                        .line(y)
+                        .map_or(x, |line| line.next_word_start(x));
+                } else if y < height {
+                    y += 1;
+                    x = 0;
+                }
+            }
+            (KeyCode::Left, _) => {
+                if x > 0 {
+                    x -= 1;
+                } else if y > 0 {
+                    y -= 1;
+                    x = line_length(y);
+                }
+            }
+            (KeyCode::Right, _) => {
+                if x < line_length(y) {
+                    x += 1;
+                } else if y < height {
+                    y += 1;
+                    x = 0;
+                }
+            }
+            _ => (),
+        }
+
+        if matches!(
+            key,
+            KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown
+        ) {
+            let preferred_x = *self.preferred_x.get_or_insert(self.cursor_position.x);
+            x = cmp::min(preferred_x, line_length(y));
+        } else {
+            self.preferred_x = None;
+        }
+        self.cursor_position = Position { x, y };
+        self.scroll()
+    }
+    /// The screen above the status and message bars, where panels are drawn.
+    fn screen_size() -> Result<Size, io::Error> {
+        let size = Terminal::size()?;
+        Ok(Size {
+            width: size.width,
+            height: size.height.saturating_sub(2),
+        })
+    }
+    /// Where every window is, all of the screen but the message bar.
+    fn window_areas(&self) -> Result<Vec<(usize, Area)>, io::Error> {
+        let size = Terminal::size()?;
+        Ok(self.layout.areas(Area {
+            x: 0,
+            y: 0,
+            width: size.width,
+            height: size.height.saturating_sub(1),
+        }))
+    }
+    /// The text area of the window being edited, above its status line.
+    fn view_size(&self) -> Result<Size, io::Error> {
+        let area = self
+            .window_areas()?
+            .into_iter()
+            .find(|(index, _)| *index == self.window)
+            .map_or(Area::default(), |(_, area)| area);
+        Ok(Size {
+            width: area.width,
+            height: area.height.saturating_sub(1),
+        })
+    }
+    fn scroll(&mut self) -> Result<(), io::Error> {
+        let Position { x, y } = self.cursor_position;
+        let size = self.view_size()?;
+        let offset = &mut self.offset;
+
+        if y < offset.y {
+            offset.y = y;
+        } else if y >= offset.y.saturating_add(size.height) {
+            offset.y = y.saturating_sub(size.height).saturating_add(1);
+        }
+        if x < offset.x {
+            offset.x = x;
+        } else if x >= offset.x.saturating_add(size.width) {
+            offset.x = x.saturating_sub(size.width).saturating_add(1);
+        }
+        Ok(())
+    }
+    pub fn welcome_msg(message: &str, width: usize) -> String {
+        let padding = width.saturating_sub(message.len()) / 2;
+        let spaces = " ".repeat(padding.saturating_sub(1));
+        let mut welcome_msg = format!("~{spaces}{message}");
+        welcome_msg.truncate(width);
+        welcome_msg
+    }
+    /// Moves to the start of row `y` of `area`, drawing the separator from
+    /// the window on its left.
+    fn move_to_row(area: Area, y: usize) -> Result<(), io::Error> {
+        if area.x > 0 {
+            Terminal::move_cursor(Position {
+                x: area.x - 1,
+                y: area.y + y,
+            })?;
+            Terminal::print("│")?;
+        }
+        Terminal::move_cursor(Position {
+            x: area.x,
+            y: area.y + y,
+        })
+    }
+    /// Draws the text of `window` in `area`, above its status line.
+    fn draw_rows(&self, window: &Window, area: Area) -> Result<(), io::Error> {
+        let document = self.buffer_document(window.buffer);
+        let size = Size {
+            width: area.width,
+            height: area.height.saturating_sub(1),
+        };
+        let start = window.offset.x;
+        let end = window.offset.x.saturating_add(size.width);
+
+        for terminal_row in 0..size.height {
+            Self::move_to_row(area, terminal_row)?;
+            Terminal::clear_until_newline()?;
+
+            let row = window.offset.y.saturating_add(terminal_row);
+            if let Some(hex) = &document.hex {
+                Terminal::print(
+                    &hex.render(row, size.width)
+                        .unwrap_or_else(|| String::from("~")),
+                )?;
+            } else if let Some(line) = document.render_line(row, start, end) {
+                Terminal::print(&line)?;
+            } else if document.is_empty() && terminal_row == size.height / 3 {
+                let version_msg = format!("{NAME} Editor -- version {VERSION}");
+                Terminal::print(&Self::welcome_msg(&version_msg, size.width))?;
+            } else if document.is_empty() && terminal_row == size.height / 3 + 1 {
+                Terminal::print(&Self::welcome_msg(
+                    "Stop Talking, Start Coding!",
+                    size.width,
+                ))?;
+            } else {
+                Terminal::print("~")?;
+            }
+        }
+        Ok(())
+    }
+    /// Draws the status line of `window` in `area`, in darker colors when it
+    /// is not being edited.
+    fn draw_status_bar(&self, window: &Window, area: Area, active: bool) -> Result<(), io::Error> {
+        let document = self.buffer_document(window.buffer);
+        let width = area.width;
+        let modified_indicator = match (document.is_dirty, document.read_only) {
+            (true, true) => " (modified) [read-only]",
+            (true, false) => " (modified)",
+            (false, true) => " [read-only]",
+            (false, false) => "",
+        };
+        let mut file_name = document
+            .file_name
+            .as_deref()
+            .unwrap_or("[No Name]")
+            .to_string();
+        if self.buffers.len() > 1 {
+            file_name = format!("[{}/{}] {file_name}", window.buffer + 1, self.buffers.len());
+        }
+        let (left, right) = if let Some(hex) = &document.hex {
+            let offset = HexBuffer::index(&window.cursor_position);
+            (
+                format!(
+                    "{file_name} - {} bytes{modified_indicator}",
+                    hex.bytes.len()
+                ),
+                format!("hex | {offset:#010x}/{:#010x}", hex.bytes.len()),
+            )
+        } else {
+            let large = match document.large.as_ref() {
+                Some(large) => match large.indexing_progress() {
+                    Some(progress) => format!(" [large file, indexing {progress}%]"),
+                    None => String::from(" [large file, no highlighting]"),
+                },
+                None if document.is_large() => String::from(" [large file, no highlighting]"),
+                None => String::new(),
+            };
+            (
+                format!(
+                    "{file_name} - {} lines{modified_indicator}{large}",
+                    document.len()
+                ),
+                format!(
+                    "{} | {} | {} | {}/{}:{}",
+                    document.file_type.name,
+                    document.encoding.name(),
+                    if document.crlf_lines.is_some() {
+                        "mixed"
+                    } else {
+                        document.line_ending.name()
+                    },
+                    window.cursor_position.y.saturating_add(1),
+                    document.len(),
+                    window.cursor_position.x.saturating_add(1),
+                ),
+            )
+        };
+
+        let left_length = left.chars().count();
+        let right_length = right.chars().count();
+        let status = if left_length + right_length < width {
+            format!(
+                "{left}{}{right}",
+                " ".repeat(width - left_length - right_length)
+            )
+        } else {
+            let mut status: String = left.chars().take(width).collect();
+            status.push_str(&" ".repeat(width.saturating_sub(left_length)));
+            status
+        };
+
+        Self::move_to_row(area, 0)?;
+        Terminal::set_bg_color(if active {
+            STATUS_BACKGROUND_COLOR
+        } else {
+            INACTIVE_STATUS_BACKGROUND_COLOR
+        })?;
+        Terminal::set_fg_color(STATUS_FG_COLOR)?;
+        Terminal::print(&status)?;
+        Terminal::reset_colors()?;
+        Ok(())
+    }
+    pub fn draw_message_bar(&self) -> Result<(), io::Error> {
+        let size = Terminal::size()?;
+        Terminal::move_cursor(Position {
+            x: 0,
+            y: size.height.saturating_sub(1),
+        })?;
+        Terminal::clear_cline()?;
+        let message = &self.status_message;
+        let width = size.width;
+        if let Some(prompt_cursor) = self.prompt_cursor {
+            let skip = prompt_cursor.saturating_add(1).saturating_sub(width);
+            let text: String = message
+                .text
+                .graphemes(true)
+                .skip(skip)
+                .take(width)
+                .collect();
+            Terminal::print(&text)?;
+        } else if message.time.elapsed() < MESSAGE_TIMEOUT {
+            let text: String = message.text.chars().take(width).collect();
+            Terminal::print(&text)?;
+        }
+        Ok(())
+    }
+}
+
+fn format_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    match seconds {
+        0..60 => format!("{seconds}s ago"),
+        60..3600 => format!("{}m ago", seconds / 60),
+        3600..86400 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
 }
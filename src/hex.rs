@@ -0,0 +1,73 @@
+use std::cmp;
+
+use crate::terminal::Position;
+
+pub const BYTES_PER_ROW: usize = 16;
+pub const NIBBLES_PER_ROW: usize = BYTES_PER_ROW * 2;
+const OFFSET_WIDTH: usize = 10;
+
+/// The raw bytes of a binary file. Positions are nibble columns (`x`, two per
+/// byte) on rows of `BYTES_PER_ROW` bytes (`y`).
+#[derive(Default)]
+pub struct HexBuffer {
+    pub bytes: Vec<u8>,
+}
+
+impl HexBuffer {
+    pub fn rows(&self) -> usize {
+        self.bytes.len().div_ceil(BYTES_PER_ROW)
+    }
+    /// Byte index of the nibble at `at`.
+    pub fn index(at: &Position) -> usize {
+        at.y * BYTES_PER_ROW + at.x / 2
+    }
+    /// Overwrites the nibble at `at` with `value` and reports whether `at`
+    /// was inside the buffer.
+    pub fn set_nibble(&mut self, at: &Position, value: u8) -> bool {
+        let Some(byte) = self.bytes.get_mut(Self::index(at)) else {
+            return false;
+        };
+        *byte = if at.x.is_multiple_of(2) {
+            (*byte & 0x0f) | (value << 4)
+        } else {
+            (*byte & 0xf0) | (value & 0x0f)
+        };
+        true
+    }
+    /// Screen column of nibble `x` in a rendered row.
+    pub fn column(x: usize) -> usize {
+        let byte = x / 2;
+        OFFSET_WIDTH + byte * 3 + usize::from(byte >= BYTES_PER_ROW / 2) + x % 2
+    }
+    /// Renders `row` as its offset, the bytes in hex and the bytes as ASCII,
+    /// cut to `width` columns.
+    pub fn render(&self, row: usize, width: usize) -> Option<String> {
+        let start = row.checked_mul(BYTES_PER_ROW)?;
+        if start >= self.bytes.len() {
+            return None;
+        }
+        let bytes = &self.bytes[start..cmp::min(start + BYTES_PER_ROW, self.bytes.len())];
+
+        let mut result = format!("{start:08x}  ");
+        for index in 0..BYTES_PER_ROW {
+            if index == BYTES_PER_ROW / 2 {
+                result.push(' ');
+            }
+            match bytes.get(index) {
+                Some(byte) => result.push_str(&format!("{byte:02x} ")),
+                None => result.push_str("   "),
+            }
+        }
+        result.push_str(" |");
+        for byte in bytes {
+            result.push(if byte.is_ascii_graphic() || *byte == b' ' {
+                char::from(*byte)
+            } else {
+                '.'
+            });
+        }
+        result.push('|');
+        result.truncate(width);
+        Some(result)
+    }
+}
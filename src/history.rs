@@ -0,0 +1,562 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fs, io};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::terminal::Position;
+
+#[derive(Clone)]
+pub enum Edit {
+    Insert { at: Position, text: String },
+    Delete { at: Position, text: String },
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Self {
+        match self {
+            Edit::Insert { at, text } => Edit::Delete {
+                at: *at,
+                text: text.clone(),
+            },
+            Edit::Delete { at, text } => Edit::Insert {
+                at: *at,
+                text: text.clone(),
+            },
+        }
+    }
+    /// Where the cursor ends up once the edit is applied.
+    pub fn end(&self) -> Position {
+        match self {
+            Edit::Insert { at, text } => match text.rsplit_once('\n') {
+                Some((head, tail)) => Position {
+                    x: tail.graphemes(true).count(),
+                    y: at.y + head.matches('\n').count() + 1,
+                },
+                None => Position {
+                    x: at.x + text.graphemes(true).count(),
+                    y: at.y,
+                },
+            },
+            Edit::Delete { at, .. } => *at,
+        }
+    }
+    /// Whether `next` continues the same word as this edit, so both can be
+    /// undone as one step.
+    fn merges_with(&self, next: &Edit) -> bool {
+        let (
+            Edit::Insert { text, .. },
+            Edit::Insert {
+                at,
+                text: next_text,
+            },
+        ) = (self, next)
+        else {
+            return false;
+        };
+        let Some(last) = text.chars().last() else {
+            return false;
+        };
+        let Some(next_char) = next_text.chars().next() else {
+            return false;
+        };
+        let starts_new_word = last.is_whitespace() && !next_char.is_whitespace();
+        *at == self.end()
+            && next_text.graphemes(true).count() == 1
+            && last != '\n'
+            && next_char != '\n'
+            && !starts_new_word
+    }
+}
+
+/// A transaction is a group of edits that is undone in one step. Undoing and
+/// then editing again starts a new branch instead of dropping the old one.
+struct Node {
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// The child `redo` follows, which is the branch visited last.
+    active_child: Option<usize>,
+    edits: Vec<Edit>,
+    time: SystemTime,
+}
+
+pub struct HistoryEntry {
+    pub id: usize,
+    pub depth: usize,
+    pub time: SystemTime,
+    pub summary: String,
+    pub is_current: bool,
+    pub is_saved: bool,
+}
+
+/// Undo tree of edit transactions. Node 0 is the unedited root.
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    saved: Option<usize>,
+    can_merge: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node {
+                parent: None,
+                children: Vec::new(),
+                active_child: None,
+                edits: Vec::new(),
+                time: SystemTime::now(),
+            }],
+            current: 0,
+            saved: Some(0),
+            can_merge: false,
+        }
+    }
+}
+
+impl History {
+    pub fn record(&mut self, edits: Vec<Edit>) {
+        if edits.is_empty() {
+            return;
+        }
+
+        let current = &self.nodes[self.current];
+        let merges = self.can_merge
+            && edits.len() == 1
+            && current.children.is_empty()
+            && self.saved != Some(self.current)
+            && current
+                .edits
+                .last()
+                .is_some_and(|last| last.merges_with(&edits[0]));
+
+        if merges {
+            let current = &mut self.nodes[self.current];
+            if let (Some(Edit::Insert { text, .. }), Edit::Insert { text: next, .. }) =
+                (current.edits.last_mut(), &edits[0])
+            {
+                text.push_str(next);
+            }
+            current.time = SystemTime::now();
+        } else {
+            let id = self.nodes.len();
+            self.nodes.push(Node {
+                parent: Some(self.current),
+                children: Vec::new(),
+                active_child: None,
+                edits,
+                time: SystemTime::now(),
+            });
+            let parent = &mut self.nodes[self.current];
+            parent.children.push(id);
+            parent.active_child = Some(id);
+            self.current = id;
+        }
+        self.can_merge = true;
+    }
+    /// Moves to the parent transaction and returns the edits that revert the
+    /// current one, in the order they have to be applied.
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        let parent = self.nodes[self.current].parent?;
+        Some(self.travel_to(parent))
+    }
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        let child = self.nodes[self.current].active_child?;
+        Some(self.travel_to(child))
+    }
+    /// Switches to the next (or previous) sibling of the current transaction,
+    /// i.e. to the state reached by another edit made after the same undo.
+    pub fn switch_branch(&mut self, forward: bool) -> Option<Vec<Edit>> {
+        let parent = self.nodes[self.current].parent?;
+        let siblings = &self.nodes[parent].children;
+        if siblings.len() < 2 {
+            return None;
+        }
+        let index = siblings.iter().position(|id| *id == self.current)?;
+        let index = if forward {
+            (index + 1) % siblings.len()
+        } else {
+            (index + siblings.len() - 1) % siblings.len()
+        };
+        Some(self.travel_to(siblings[index]))
+    }
+    /// Moves to the newest state that existed `seconds` before the current
+    /// one, or after it unless `earlier`, whichever branch it is on.
+    pub fn time_travel(&mut self, seconds: u64, earlier: bool) -> Option<Vec<Edit>> {
+        let now = self.nodes[self.current].time;
+        let delta = Duration::from_secs(seconds);
+        // Further than the clock reaches is the oldest or the newest state.
+        let target_time = if earlier {
+            Some(now.checked_sub(delta).unwrap_or(UNIX_EPOCH))
+        } else {
+            now.checked_add(delta)
+        };
+        let target = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| target_time.is_none_or(|time| node.time <= time))
+            .max_by_key(|(id, node)| (node.time, *id))
+            .map_or(0, |(id, _)| id);
+        if target == self.current {
+            return None;
+        }
+        Some(self.travel_to(target))
+    }
+    fn path_to_root(&self, mut id: usize) -> Vec<usize> {
+        let mut path = vec![id];
+        while let Some(parent) = self.nodes[id].parent {
+            path.push(parent);
+            id = parent;
+        }
+        path
+    }
+    /// Makes `target` the current transaction and returns the edits that
+    /// turn the current state into the target's.
+    pub fn travel_to(&mut self, target: usize) -> Vec<Edit> {
+        let mut up = self.path_to_root(self.current);
+        let mut down = self.path_to_root(target);
+        while let (Some(a), Some(b)) = (up.last(), down.last()) {
+            if a != b {
+                break;
+            }
+            up.pop();
+            down.pop();
+        }
+
+        let mut edits: Vec<Edit> = Vec::new();
+        for id in &up {
+            edits.extend(self.nodes[*id].edits.iter().rev().map(Edit::inverse));
+        }
+        for id in down.iter().rev() {
+            edits.extend(self.nodes[*id].edits.iter().cloned());
+            if let Some(parent) = self.nodes[*id].parent {
+                self.nodes[parent].active_child = Some(*id);
+            }
+        }
+        self.current = target;
+        self.can_merge = false;
+        edits
+    }
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.current);
+        self.can_merge = false;
+    }
+    pub fn is_dirty(&self) -> bool {
+        self.saved != Some(self.current)
+    }
+    /// Lists the transactions depth-first, indenting each branch that forks
+    /// off an earlier one.
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        let mut entries = Vec::new();
+        let mut stack = vec![(0, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            let node = &self.nodes[id];
+            let summary = node
+                .edits
+                .iter()
+                .map(|edit| match edit {
+                    Edit::Insert { text, .. } => format!("+{}", escape(text)),
+                    Edit::Delete { text, .. } => format!("-{}", escape(text)),
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            entries.push(HistoryEntry {
+                id,
+                depth,
+                time: node.time,
+                summary,
+                is_current: id == self.current,
+                is_saved: self.saved == Some(id),
+            });
+            for (index, child) in node.children.iter().enumerate().rev() {
+                stack.push((*child, depth + usize::from(index > 0)));
+            }
+        }
+        entries
+    }
+}
+
+const UNDO_FILE_HEADER: &str = "guard-undo 2";
+
+/// FNV-1a hash of a file's contents, used to tell whether a persisted undo
+/// history still belongs to the file on disk.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    extend_hash(0xcbf2_9ce4_8422_2325, bytes)
+}
+
+/// Continues a `content_hash` over more bytes, for contents that are kept
+/// in chunks rather than one slice.
+pub fn extend_hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn undo_dir() -> Option<PathBuf> {
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(cache.join("guard").join("undo"))
+}
+
+fn undo_file(file_name: &str) -> Option<(PathBuf, String)> {
+    let path = fs::canonicalize(file_name).ok()?;
+    let path = path.to_string_lossy().into_owned();
+    let key = content_hash(path.as_bytes());
+    Some((undo_dir()?.join(format!("{key:016x}.undo")), path))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+fn parse_edit(line: &str) -> Option<Edit> {
+    let mut parts = line.splitn(4, ' ');
+    let kind = parts.next()?;
+    let y = parts.next()?.parse().ok()?;
+    let x = parts.next()?.parse().ok()?;
+    let at = Position { x, y };
+    let text = unescape(parts.next()?);
+    match kind {
+        "+" => Some(Edit::Insert { at, text }),
+        "-" => Some(Edit::Delete { at, text }),
+        _ => None,
+    }
+}
+
+fn parse_node(line: &str) -> Option<Node> {
+    let mut parts = line.split(' ');
+    if parts.next()? != "n" {
+        return None;
+    }
+    let parent = match parts.next()? {
+        "-" => None,
+        parent => Some(parent.parse().ok()?),
+    };
+    let active_child = match parts.next()? {
+        "-" => None,
+        child => Some(child.parse().ok()?),
+    };
+    let millis: u64 = parts.next()?.parse().ok()?;
+    Some(Node {
+        parent,
+        children: Vec::new(),
+        active_child,
+        edits: Vec::new(),
+        time: UNIX_EPOCH + Duration::from_millis(millis),
+    })
+}
+
+impl History {
+    /// Writes the history for `file_name` to the cache directory, to be
+    /// restored at the save point, which is what the file on disk holds.
+    pub fn persist(&self, file_name: &str, content_hash: u64) -> Result<(), io::Error> {
+        let (Some((undo_file, path)), Some(saved)) = (undo_file(file_name), self.saved) else {
+            return Ok(());
+        };
+
+        let mut contents = format!("{UNDO_FILE_HEADER}\n{path}\n{content_hash:016x}\n{saved}\n");
+        for node in &self.nodes {
+            let parent = node.parent.map_or(String::from("-"), |id| id.to_string());
+            let child = node
+                .active_child
+                .map_or(String::from("-"), |id| id.to_string());
+            let millis = node
+                .time
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_millis());
+            contents.push_str(&format!("n {parent} {child} {millis}\n"));
+            for edit in &node.edits {
+                let (kind, at, text) = match edit {
+                    Edit::Insert { at, text } => ('+', at, text),
+                    Edit::Delete { at, text } => ('-', at, text),
+                };
+                contents.push_str(&format!("{kind} {} {} {}\n", at.y, at.x, escape(text)));
+            }
+        }
+
+        if let Some(parent) = undo_file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(undo_file, contents)
+    }
+    /// Loads the history persisted for `file_name`. A history that was
+    /// written for different contents is deleted instead.
+    pub fn restore(file_name: &str, content_hash: u64) -> Option<Self> {
+        let (undo_file, path) = undo_file(file_name)?;
+        let contents = fs::read_to_string(&undo_file).ok()?;
+        let mut lines = contents.lines();
+
+        let header = lines.next()?;
+        let stored_path = lines.next()?;
+        let stored_hash = lines.next()?;
+        if header != UNDO_FILE_HEADER
+            || stored_path != path
+            || stored_hash != format!("{content_hash:016x}")
+        {
+            let _ = fs::remove_file(&undo_file);
+            return None;
+        }
+        let saved: usize = lines.next()?.parse().ok()?;
+
+        let mut nodes: Vec<Node> = Vec::new();
+        for line in lines {
+            if line.starts_with("n ") {
+                nodes.push(parse_node(line)?);
+            } else {
+                nodes.last_mut()?.edits.push(parse_edit(line)?);
+            }
+        }
+        for id in 0..nodes.len() {
+            let parent = nodes[id].parent;
+            if let Some(parent) = parent {
+                if parent >= id {
+                    return None;
+                }
+                nodes[parent].children.push(id);
+            }
+        }
+        if saved >= nodes.len() || nodes.first()?.parent.is_some() {
+            return None;
+        }
+        for node in &mut nodes {
+            if node
+                .active_child
+                .is_some_and(|id| !node.children.contains(&id))
+            {
+                node.active_child = node.children.last().copied();
+            }
+        }
+
+        Some(Self {
+            nodes,
+            current: saved,
+            saved: Some(saved),
+            can_merge: false,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::process;
+    use std::sync::Once;
+
+    /// A directory for the tests' files, whose histories are persisted
+    /// under a cache directory of the tests' own.
+    pub(crate) fn test_dir() -> PathBuf {
+        static CACHE: Once = Once::new();
+        let dir = env::temp_dir().join(format!("guard-history-{}", process::id()));
+        CACHE.call_once(|| env::set_var("XDG_CACHE_HOME", dir.join("cache")));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A file to persist a history for.
+    fn test_file(name: &str) -> String {
+        let file = test_dir().join(name);
+        fs::write(&file, "text\n").unwrap();
+        file.to_string_lossy().into_owned()
+    }
+
+    fn insert(y: usize, x: usize, text: &str) -> Vec<Edit> {
+        vec![Edit::Insert {
+            at: Position { x, y },
+            text: text.to_string(),
+        }]
+    }
+
+    fn summaries(history: &History) -> Vec<(usize, String, bool, bool)> {
+        history
+            .entries()
+            .into_iter()
+            .map(|entry| (entry.depth, entry.summary, entry.is_current, entry.is_saved))
+            .collect()
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        for text in ["plain", "two\nlines\r\n", "back\\slash\\n", "\\", "end\\"] {
+            assert_eq!(unescape(&escape(text)), text);
+            assert!(!escape(text).contains('\n'));
+        }
+    }
+
+    #[test]
+    fn persist_and_restore_keep_branches() {
+        let file_name = test_file("branches.txt");
+        let mut history = History::default();
+        history.record(insert(0, 0, "first\n"));
+        history.undo();
+        history.record(insert(0, 0, "a \\n b\r"));
+        history.record(insert(1, 2, "more"));
+        history.mark_saved();
+        history.persist(&file_name, 42).unwrap();
+
+        let mut restored = History::restore(&file_name, 42).unwrap();
+        assert_eq!(summaries(&restored), summaries(&history));
+        assert!(!restored.is_dirty());
+
+        let edits = restored.undo().unwrap();
+        assert!(matches!(
+            edits.as_slice(),
+            [Edit::Delete { at: Position { x: 2, y: 1 }, text }] if text == "more"
+        ));
+        let edits = restored.switch_branch(true).unwrap();
+        assert!(matches!(
+            edits.as_slice(),
+            [Edit::Delete { text: removed, .. }, Edit::Insert { text: inserted, .. }]
+                if removed == "a \\n b\r" && inserted == "first\n"
+        ));
+    }
+
+    #[test]
+    fn time_travel_beyond_the_clock_reaches_the_ends() {
+        let mut history = History::default();
+        history.record(insert(0, 0, "a"));
+        history.record(insert(0, 5, "b"));
+
+        let edits = history.time_travel(u64::MAX, true).unwrap();
+        assert_eq!(edits.len(), 2);
+        assert!(history.time_travel(u64::MAX, true).is_none());
+        let edits = history.time_travel(u64::MAX, false).unwrap();
+        assert_eq!(edits.len(), 2);
+        assert!(history.time_travel(u64::MAX, false).is_none());
+    }
+
+    #[test]
+    fn restore_deletes_a_history_for_other_contents() {
+        let file_name = test_file("changed.txt");
+        let mut history = History::default();
+        history.record(insert(0, 0, "x"));
+        history.mark_saved();
+        history.persist(&file_name, 1).unwrap();
+        let (undo_file, _) = undo_file(&file_name).unwrap();
+        assert!(undo_file.exists());
+
+        assert!(History::restore(&file_name, 2).is_none());
+        assert!(!undo_file.exists());
+        assert!(History::restore(&file_name, 1).is_none());
+    }
+}
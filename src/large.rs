@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Files from this size on are opened as a `LargeFile` instead of being
+/// read into the buffer.
+pub const LARGE_FILE_SIZE: u64 = 128 * 1024 * 1024;
+/// How often the screen is redrawn while the lines are still being indexed.
+pub const INDEX_REFRESH: Duration = Duration::from_millis(250);
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Byte offsets of the line starts found so far, and how far into the file
+/// the search has got.
+struct LineIndex {
+    starts: Mutex<Vec<u64>>,
+    indexed: AtomicU64,
+}
+
+/// A file too large to load, whose lines are read from disk as they are
+/// shown. A background thread indexes where the lines start, so the top of
+/// the file shows up before the whole of it has been read. Reading the file
+/// instead of mapping it keeps a file truncated by another program from
+/// crashing the editor.
+pub struct LargeFile {
+    file: File,
+    size: u64,
+    index: Arc<LineIndex>,
+}
+
+impl LargeFile {
+    /// Starts indexing `file_name`, whose text begins `start` bytes in, after
+    /// its BOM.
+    pub fn open(file_name: &str, start: u64) -> Result<Self, io::Error> {
+        let file = File::open(file_name)?;
+        let size = file.metadata()?.len();
+        let index = Arc::new(LineIndex {
+            starts: Mutex::new(vec![start]),
+            indexed: AtomicU64::new(start),
+        });
+
+        // The thread reads through a file of its own, so it does not move
+        // the position `line` seeks from.
+        let mut reader = File::open(file_name)?;
+        reader.seek(SeekFrom::Start(start))?;
+        let thread_index = Arc::clone(&index);
+        thread::spawn(move || {
+            let mut buffer = vec![0; CHUNK_SIZE];
+            let mut offset = start;
+            // Stops early once the document is gone.
+            while Arc::strong_count(&thread_index) > 1 {
+                let read = match reader.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => read,
+                };
+                let starts: Vec<u64> = memchr::memchr_iter(b'\n', &buffer[..read])
+                    .map(|at| offset + at as u64 + 1)
+                    .filter(|start| *start < size)
+                    .collect();
+                offset += read as u64;
+                if let Ok(mut all) = thread_index.starts.lock() {
+                    all.extend(starts);
+                }
+                thread_index.indexed.store(offset, Ordering::Release);
+            }
+            // A file that shrank or failed to read ends where it stopped.
+            thread_index.indexed.store(size, Ordering::Release);
+        });
+
+        Ok(Self { file, size, index })
+    }
+    fn is_indexed(&self) -> bool {
+        self.index.indexed.load(Ordering::Acquire) >= self.size
+    }
+    /// How much of the file is indexed, in percent, while it is not done.
+    pub fn indexing_progress(&self) -> Option<u64> {
+        let indexed = self.index.indexed.load(Ordering::Acquire);
+        (indexed < self.size).then(|| indexed * 100 / self.size)
+    }
+    /// Number of lines indexed so far. The last line found is only counted
+    /// once its end is known.
+    pub fn len(&self) -> usize {
+        let is_indexed = self.is_indexed();
+        let starts = self.index.starts.lock().map_or(0, |starts| starts.len());
+        if is_indexed {
+            starts
+        } else {
+            starts.saturating_sub(1)
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Reads line `y` without its line break. Bytes that are not UTF-8 are
+    /// shown as replacement characters.
+    pub fn line(&self, y: usize) -> Option<String> {
+        if y >= self.len() {
+            return None;
+        }
+        let (start, end) = {
+            let starts = self.index.starts.lock().ok()?;
+            (
+                *starts.get(y)?,
+                starts.get(y + 1).copied().unwrap_or(self.size),
+            )
+        };
+        let mut bytes = vec![0; usize::try_from(end - start).ok()?];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(start)).ok()?;
+        file.read_exact(&mut bytes).ok()?;
+
+        let line = bytes.strip_suffix(b"\n").unwrap_or(&bytes);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        Some(String::from_utf8_lossy(line).into_owned())
+    }
+}
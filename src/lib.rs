@@ -0,0 +1,13 @@
+pub mod arguments;
+pub mod documents;
+pub mod editor;
+pub mod files;
+pub mod hex;
+pub mod highlights;
+pub mod history;
+pub mod large;
+pub mod lines;
+pub mod panel;
+pub mod swap;
+pub mod terminal;
+pub mod windows;
@@ -1,9 +1,13 @@
-use crossterm::style::Color;
-use std::char;
+use crossterm::style::{Color, SetForegroundColor};
+use crossterm::Command;
+use regex::Regex;
+use std::cmp;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
-    editor::SearchDirection, files::HighLightsOptions, highlights::Type, terminal::Position,
+    editor::{SearchDirection, SearchOptions},
+    files::HighLightsOptions,
+    highlights::Type,
 };
 
 #[derive(Default)]
@@ -26,33 +30,56 @@ impl From<&str> for Line {
 }
 
 impl Line {
+    pub fn render(&self, start: usize, end: usize) -> String {
+        let end = cmp::min(end, self.length);
+        let start = cmp::min(start, end);
+        let mut result = String::new();
+        let mut current_highlighting: Option<Type> = None;
+
+        for (index, grapheme) in self.string[..]
+            .graphemes(true)
+            .enumerate()
+            .skip(start)
+            .take(end - start)
+        {
+            let highlighting_type = self.highlighting.get(index).copied().unwrap_or(Type::None);
+            if current_highlighting != Some(highlighting_type) {
+                current_highlighting = Some(highlighting_type);
+                let _ = SetForegroundColor(highlighting_type.to_color()).write_ansi(&mut result);
+            }
+            if grapheme == "\t" {
+                result.push(' ');
+            } else {
+                result.push_str(grapheme);
+            }
+        }
+        let _ = SetForegroundColor(Color::Reset).write_ansi(&mut result);
+        result
+    }
     pub fn highlight(
         &mut self,
         options: &HighLightsOptions,
-        word: &Option<String>,
+        word: &Option<Regex>,
+        search_options: &SearchOptions,
         start_with_comment: bool,
     ) -> bool {
-        let chars: Vec<char> = self.string.chars().collect();
-
         if self.is_highlighted && word.is_none() {
             if let Some(hl_type) = self.highlighting.last() {
-                if *hl_type == Type::MultilineComment
-                    && self.string.len() > 1
-                    && self.string[self.string.len() - 2..] == *"*/"
-                {
+                if *hl_type == Type::MultilineComment && self.string.ends_with("*/") {
                     return true;
                 }
             }
             return false;
         }
 
+        let chars: Vec<char> = self.string.chars().collect();
         self.highlighting = Vec::new();
 
         let mut index = 0;
         let mut inside_ml_comment = start_with_comment;
 
         if inside_ml_comment {
-            let closing_index = if let Some(closing_index) = self.string.find("*/") {
+            let closing_index = if let Some(closing_index) = Self::find_comment_end(&chars, 0) {
                 closing_index + 2
             } else {
                 chars.len()
@@ -83,29 +110,82 @@ impl Line {
             self.highlighting.push(Type::None);
             index += 1;
         }
-        self.highlight_match(word);
-        if inside_ml_comment && &self.string[self.string.len().saturating_sub(2)..] != "*/" {
+        self.highlight_match(word, search_options);
+        if inside_ml_comment && !self.string.ends_with("*/") {
             return true;
         }
         self.is_highlighted = true;
         false
     }
-    pub fn highlight_match(&mut self, word: &Option<String>) {
-        if let Some(word) = word {
-            if word.is_empty() {
-                return;
+    /// Whether the line after `line` starts inside a multiline comment, as
+    /// `highlight` would return, without highlighting anything. It only
+    /// stops at quotes and slashes: keywords and numbers never hold them, and
+    /// they are all ASCII, so no other char can be mistaken for one.
+    pub fn ends_in_comment(
+        line: &str,
+        options: &HighLightsOptions,
+        start_with_comment: bool,
+    ) -> bool {
+        let bytes = line.as_bytes();
+        let comment_end = |from: usize| {
+            memchr::memmem::find(&bytes[from..], b"*/").map_or(bytes.len(), |end| from + end + 2)
+        };
+        // As in `highlight`, only a comment that runs to the end of the line
+        // carries over: anything after it closes the state again.
+        let mut index = if start_with_comment {
+            comment_end(0)
+        } else {
+            0
+        };
+        let mut inside_ml_comment = start_with_comment && index >= bytes.len();
+
+        while let Some(found) = bytes
+            .get(index..)
+            .and_then(|rest| memchr::memchr3(b'/', b'"', b'\'', rest))
+        {
+            index += found;
+            let next_byte = bytes.get(index + 1).copied();
+            if options.comments && bytes[index] == b'/' && next_byte == Some(b'*') {
+                index = comment_end(index + 2);
+                inside_ml_comment = index >= bytes.len();
+                continue;
             }
+            match bytes[index] {
+                b'\'' if options.character => {
+                    let mut rest = line[index + 1..].char_indices();
+                    if let Some((_, '\\')) = rest.next() {
+                        rest.next();
+                    }
+                    if let Some((end, '\'')) = rest.next() {
+                        index += end + 2;
+                        continue;
+                    }
+                }
+                b'/' if options.comments && next_byte == Some(b'/') => break,
+                b'"' if options.strings => {
+                    index = memchr::memchr(b'"', &bytes[index + 1..])
+                        .map_or(bytes.len(), |end| index + 1 + end);
+                }
+                _ => (),
+            }
+            index += 1;
+        }
+        inside_ml_comment && !line.ends_with("*/")
+    }
+    pub fn highlight_match(&mut self, word: &Option<Regex>, search_options: &SearchOptions) {
+        if let Some(word) = word {
+            let options = SearchOptions {
+                direction: SearchDirection::Forward,
+                ..*search_options
+            };
             let mut index = 0;
-            while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
-                if let Some(nex_index) = search_match.checked_add(word[..].graphemes(true).count())
-                {
-                    for i in search_match..nex_index {
-                        self.highlighting[i] = Type::Match;
+            while let Some((search_match, length)) = self.find(word, index, &options) {
+                for i in search_match..search_match.saturating_add(length) {
+                    if let Some(highlighting) = self.highlighting.get_mut(i) {
+                        *highlighting = Type::Match;
                     }
-                    index = nex_index;
-                } else {
-                    break;
                 }
+                index = search_match.saturating_add(cmp::max(length, 1));
             }
         }
     }
@@ -156,7 +236,7 @@ impl Line {
                 }
             }
 
-            if self.highlight_str(index, &word, chars, hl_type) {
+            if self.highlight_str(index, word, chars, hl_type) {
                 return true;
             }
         }
@@ -249,12 +329,13 @@ impl Line {
         if options.comments && c == '/' && *index < chars.len() {
             if let Some(next_char) = chars.get(index.saturating_add(1)) {
                 if *next_char == '*' {
-                    let closing_index =
-                        if let Some(closing_index) = self.string[*index + 2..].find("*/") {
-                            *index + closing_index + 4
-                        } else {
-                            chars.len()
-                        };
+                    let closing_index = if let Some(closing_index) =
+                        Self::find_comment_end(chars, index.saturating_add(2))
+                    {
+                        closing_index + 2
+                    } else {
+                        chars.len()
+                    };
                     for _ in *index..closing_index {
                         self.highlighting.push(Type::MultilineComment);
                         *index += 1;
@@ -266,6 +347,14 @@ impl Line {
         false
     }
 
+    fn find_comment_end(chars: &[char], from: usize) -> Option<usize> {
+        chars
+            .get(from..)?
+            .windows(2)
+            .position(|pair| pair == ['*', '/'])
+            .map(|position| from + position)
+    }
+
     pub fn highlight_strings(
         &mut self,
         index: &mut usize,
@@ -322,109 +411,181 @@ impl Line {
         }
         false
     }
-    pub fn split(&mut self, at: usize) -> Self {
-        let mut line = String::new();
-
-        let mut length = 0;
-        let mut splitted_line = String::new();
-        let mut splitted_length = 0;
-
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if index < at {
-                length += 1;
-                line.push_str(grapheme);
-            } else {
-                splitted_length += 1;
-                splitted_line.push_str(grapheme);
-            }
-        }
-        self.string = line;
-        self.length = length;
-        self.is_highlighted = false;
-        Self {
-            string: splitted_line,
-            length: splitted_length,
-            is_highlighted: false,
-            highlighting: Vec::new(),
-        }
-    }
-    pub fn append(&mut self, new_line: &Self) {
-        self.string = format!("{}{}", self.string, new_line.string);
-        self.length += new_line.length;
-    }
-
     pub fn delete(&mut self, at: usize) {
         if at >= self.length {
             return;
         }
-        let mut end_string = String::new();
-        let mut end_length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if index != at {
-                end_length += 1;
-                end_string.push_str(grapheme);
-            }
-        }
-        self.length = end_length;
-        self.string = end_string;
+        let start = self.byte_index(at);
+        let end = self.byte_index(at + 1);
+        self.string.replace_range(start..end, "");
+        self.length -= 1;
+        self.is_highlighted = false;
     }
 
-    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
-        if at > self.length || query.is_empty() {
+    fn byte_index(&self, at: usize) -> usize {
+        self.string
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(index, _)| index)
+    }
+    fn grapheme_index(&self, byte_index: usize) -> usize {
+        self.string
+            .grapheme_indices(true)
+            .take_while(|(index, _)| *index < byte_index)
+            .count()
+    }
+    fn is_word_boundary(&self, byte_index: usize) -> bool {
+        byte_index == self.string.len()
+            || self
+                .string
+                .split_word_bound_indices()
+                .any(|(index, _)| index == byte_index)
+    }
+    /// Finds the next match of `query` from `at` in `options.direction` and
+    /// returns the grapheme index and grapheme length of the match.
+    pub fn find(
+        &self,
+        query: &Regex,
+        at: usize,
+        options: &SearchOptions,
+    ) -> Option<(usize, usize)> {
+        if at > self.length {
             return None;
         }
-        let start = match direction {
-            SearchDirection::Forward => at,
-            SearchDirection::Backward => 0,
-        };
-        let end = match direction {
-            SearchDirection::Forward => self.length,
-            SearchDirection::Backward => at,
+        let byte_at = self.byte_index(at);
+        let is_match = |found: &regex::Match| {
+            !options.whole_word
+                || (!found.is_empty()
+                    && self.is_word_boundary(found.start())
+                    && self.is_word_boundary(found.end()))
         };
-        let substring: String = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
-
-        match direction {
+        let found = match options.direction {
             SearchDirection::Forward => {
-                if let Some(match_idx) = substring.find(query) {
-                    let grapheme_count = substring[..match_idx].graphemes(true).count();
-                    Some(start + grapheme_count)
-                } else {
-                    None
+                let mut from = byte_at;
+                loop {
+                    let found = query.find_at(&self.string, from)?;
+                    if is_match(&found) {
+                        break found;
+                    }
+                    from = found.start() + self.string[found.start()..].chars().next()?.len_utf8();
                 }
             }
-            SearchDirection::Backward => {
-                if let Some(match_idx) = substring.rfind(query) {
-                    let grapheme_count = substring[..match_idx].graphemes(true).count();
-                    Some(start + grapheme_count)
-                } else {
-                    None
-                }
+            SearchDirection::Backward => query
+                .find_iter(&self.string)
+                .take_while(|found| found.start() < byte_at)
+                .filter(is_match)
+                .last()?,
+        };
+        let start = self.grapheme_index(found.start());
+        let end = self.grapheme_index(found.end());
+        Some((start, end.saturating_sub(start)))
+    }
+    /// Returns the text matched by `regex` at `at` together with the text
+    /// that replaces it. With `expand`, `$1`-style capture references in
+    /// `replacement` are substituted.
+    pub fn match_replacement(
+        &self,
+        regex: &Regex,
+        at: usize,
+        replacement: &str,
+        expand: bool,
+    ) -> Option<(String, String)> {
+        let byte_at = self.byte_index(at);
+        let captures = regex.captures_at(&self.string, byte_at)?;
+        let found = captures.get(0)?;
+        if found.start() != byte_at {
+            return None;
+        }
+
+        let mut text = String::new();
+        if expand {
+            captures.expand(replacement, &mut text);
+        } else {
+            text.push_str(replacement);
+        }
+        Some((found.as_str().to_string(), text))
+    }
+    pub fn grapheme_at(&self, at: usize) -> Option<&str> {
+        self.string.graphemes(true).nth(at)
+    }
+    pub fn insert_str(&mut self, at: usize, string: &str) {
+        let byte_at = self.byte_index(at);
+        self.string.insert_str(byte_at, string);
+        self.length = self.string.graphemes(true).count();
+        self.is_highlighted = false;
+    }
+    fn word_starts(&self) -> Vec<usize> {
+        let mut starts = Vec::new();
+        let mut index = 0;
+        for word in self.string.split_word_bounds() {
+            if !word.trim().is_empty() {
+                starts.push(index);
             }
+            index += word.graphemes(true).count();
         }
+        starts
+    }
+    pub fn next_word_start(&self, at: usize) -> usize {
+        self.word_starts()
+            .into_iter()
+            .find(|start| *start > at)
+            .unwrap_or(self.length)
+    }
+    pub fn previous_word_start(&self, at: usize) -> usize {
+        self.word_starts()
+            .into_iter()
+            .rev()
+            .find(|start| *start < at)
+            .unwrap_or(0)
     }
     pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.length {
-            self.string.push(c);
-            self.length += 1;
-            return;
-        }
+        let mut buffer = [0; 4];
+        self.insert_str(at, c.encode_utf8(&mut buffer));
+    }
+}
 
-        let mut result = String::new();
-        let mut length = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::FileType;
 
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            length += 1;
-            if index == at {
-                length += 1;
-                result.push(c);
+    #[test]
+    fn ends_in_comment_agrees_with_highlight() {
+        let options = FileType::from("test.rs").highlights;
+        let lines = [
+            "",
+            "let x = 1;",
+            "/* open",
+            "/* closed */",
+            "/* closed */ after",
+            "a /* b */ /* c",
+            "still inside",
+            "closes */ here",
+            "*/",
+            "/*/",
+            "\"/* in a string\"",
+            "\"unterminated /*",
+            "'/' /* after a char",
+            "'\\'' /* after an escaped char",
+            "'é' /* after a wide char",
+            "// line comment /* not a block",
+            "x / y /* ratio",
+            "é /* wide text first",
+        ];
+        for line in lines {
+            for start_with_comment in [false, true] {
+                let highlighted = Line::from(line).highlight(
+                    &options,
+                    &None,
+                    &SearchOptions::default(),
+                    start_with_comment,
+                );
+                assert_eq!(
+                    Line::ends_in_comment(line, &options, start_with_comment),
+                    highlighted,
+                    "{line:?} starting inside a comment: {start_with_comment}"
+                );
             }
-            result.push_str(grapheme);
         }
-        self.length = length;
-        self.string = result;
     }
 }
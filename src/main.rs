@@ -1,12 +1,15 @@
-use editor::Editor;
-pub mod documents;
-pub mod editor;
-pub mod files;
-pub mod highlights;
-pub mod lines;
-pub mod terminal;
+use guard::arguments::{self, Command};
+use guard::editor::Editor;
+use std::{env, process};
 
 fn main() {
-    let mut editor = Editor::default();
-    editor.run();
+    match arguments::parse(env::args().skip(1)) {
+        Ok(Command::Edit(arguments)) => Editor::new(arguments).run(),
+        Ok(Command::Help) => print!("{}", arguments::USAGE),
+        Ok(Command::Version) => println!("{}", arguments::VERSION),
+        Err(err) => {
+            eprintln!("guard: {err}\nTry 'guard --help' for more information.");
+            process::exit(2);
+        }
+    }
 }
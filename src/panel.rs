@@ -0,0 +1,59 @@
+use crate::editor::{STATUS_BACKGROUND_COLOR, STATUS_FG_COLOR};
+use crate::terminal::{Size, Terminal};
+use crossterm::event::KeyCode;
+use std::cmp;
+use std::io;
+
+/// A selectable list drawn over the text area, such as the undo tree.
+pub struct Panel {
+    pub title: String,
+    pub items: Vec<String>,
+    pub selected: usize,
+}
+
+impl Panel {
+    pub fn move_selection(&mut self, key: KeyCode, page: usize) {
+        let last = self.items.len().saturating_sub(1);
+        self.selected = match key {
+            KeyCode::Up => self.selected.saturating_sub(1),
+            KeyCode::Down => cmp::min(self.selected.saturating_add(1), last),
+            KeyCode::PageUp => self.selected.saturating_sub(page),
+            KeyCode::PageDown => cmp::min(self.selected.saturating_add(page), last),
+            KeyCode::Home => 0,
+            KeyCode::End => last,
+            _ => self.selected,
+        };
+    }
+    /// First item shown, so the selection stays inside `height` rows below
+    /// the title.
+    pub fn offset(&self, height: usize) -> usize {
+        let rows = cmp::max(height.saturating_sub(1), 1);
+        self.selected.saturating_add(1).saturating_sub(rows)
+    }
+    pub fn draw(&self, size: Size) -> Result<(), io::Error> {
+        let offset = self.offset(size.height);
+        for terminal_row in 0..size.height {
+            Terminal::clear_cline()?;
+            let (text, highlighted) = if terminal_row == 0 {
+                (self.title.as_str(), true)
+            } else if let Some(item) = self.items.get(offset + terminal_row - 1) {
+                (item.as_str(), offset + terminal_row - 1 == self.selected)
+            } else {
+                ("~", false)
+            };
+
+            let mut text: String = text.chars().take(size.width).collect();
+            if highlighted {
+                text.push_str(&" ".repeat(size.width.saturating_sub(text.chars().count())));
+                Terminal::set_bg_color(STATUS_BACKGROUND_COLOR)?;
+                Terminal::set_fg_color(STATUS_FG_COLOR)?;
+                Terminal::print(&text)?;
+                Terminal::reset_colors()?;
+            } else {
+                Terminal::print(&text)?;
+            }
+            Terminal::print("\r\n")?;
+        }
+        Ok(())
+    }
+}
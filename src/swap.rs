@@ -0,0 +1,193 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{cmp, fs, io, io::Write, process};
+
+pub const SWAP_INTERVAL: Duration = Duration::from_secs(2);
+const SWAP_FILE_HEADER: &str = "guard-swap 1";
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Unsaved text found in a swap file whose editor is no longer running.
+pub struct Leftover {
+    pub pid: u32,
+    pub text: String,
+}
+
+/// The swap file kept next to a dirty buffer, so the edits survive a crash.
+/// It holds the PID of the editor writing it; a swap file that belongs to
+/// another `owner`, such as a running editor, is never written or removed.
+pub struct Swap {
+    path: PathBuf,
+    pub owner: Option<u32>,
+    pub leftover: Option<Leftover>,
+    written: Option<(usize, Instant)>,
+}
+
+fn swap_file(file_name: &str) -> Option<PathBuf> {
+    let path = Path::new(file_name);
+    let name = path.file_name()?.to_string_lossy();
+    Some(path.with_file_name(format!(".{name}.guard-swp")))
+}
+
+/// Whether process `pid` is still alive.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks that the process exists and may be signalled.
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a way to ask, every swap file is treated as left over.
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+impl Swap {
+    /// Looks for the swap file of `file_name`, whose current contents on
+    /// disk are `disk_text`. A swap file holding that same text is stale and
+    /// removed right away.
+    pub fn open(file_name: &str, disk_text: &str) -> Option<Self> {
+        let path = swap_file(file_name)?;
+        let mut swap = Self {
+            path,
+            owner: None,
+            leftover: None,
+            written: None,
+        };
+        let Ok(contents) = fs::read_to_string(&swap.path) else {
+            return Some(swap);
+        };
+
+        let mut parts = contents.splitn(3, '\n');
+        let (Some(SWAP_FILE_HEADER), Some(pid), Some(text)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            let _ = fs::remove_file(&swap.path);
+            return Some(swap);
+        };
+        let Ok(pid) = pid.parse::<u32>() else {
+            let _ = fs::remove_file(&swap.path);
+            return Some(swap);
+        };
+        if pid != process::id() && is_running(pid) {
+            swap.owner = Some(pid);
+        } else if text == disk_text {
+            let _ = fs::remove_file(&swap.path);
+        } else {
+            swap.leftover = Some(Leftover {
+                pid,
+                text: text.to_string(),
+            });
+        }
+        Some(swap)
+    }
+    /// How long until the buffer at `changes` should be written, or `None`
+    /// when the swap file is already up to date.
+    pub fn wait(&self, changes: usize) -> Option<Duration> {
+        if self.owner.is_some() {
+            return None;
+        }
+        match self.written {
+            Some((written, _)) if written == changes => None,
+            Some((_, time)) => Some(SWAP_INTERVAL.saturating_sub(time.elapsed())),
+            None => Some(Duration::ZERO),
+        }
+    }
+    /// Writes the buffer's `chunks` as of `changes`. The file is only
+    /// readable by its owner, whatever the edited file allows.
+    pub fn write<'a>(
+        &mut self,
+        changes: usize,
+        chunks: impl Iterator<Item = &'a str>,
+    ) -> Result<(), io::Error> {
+        if self.owner.is_some() {
+            return Ok(());
+        }
+        self.written = Some((changes, Instant::now()));
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path)?;
+        file.write_all(format!("{SWAP_FILE_HEADER}\n{}\n", process::id()).as_bytes())?;
+        for chunk in chunks {
+            file.write_all(chunk.as_bytes())?;
+        }
+        file.sync_data()
+    }
+    /// Removes the swap file written for this buffer once it has nothing
+    /// left to recover.
+    pub fn remove(&mut self) {
+        if self.owner.is_none() && self.written.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+    /// Removes the swap file whoever wrote it, unless its editor still runs.
+    pub fn discard(&mut self) {
+        if self.owner.is_none() {
+            self.written = None;
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Lines removed from `old` and added in `new`, each prefixed with `-` or
+/// `+` and its line number. Very large changes are listed whole instead of
+/// being matched line by line.
+pub fn diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_changed = &old[prefix..old.len() - suffix];
+    let new_changed = &new[prefix..new.len() - suffix];
+
+    let removed = |index: usize| format!("-{:>6} {}", prefix + index + 1, old_changed[index]);
+    let added = |index: usize| format!("+{:>6} {}", prefix + index + 1, new_changed[index]);
+    if old_changed.len().saturating_mul(new_changed.len()) > MAX_DIFF_CELLS {
+        return (0..old_changed.len())
+            .map(removed)
+            .chain((0..new_changed.len()).map(added))
+            .collect();
+    }
+
+    // Longest common subsequence of the changed middle, filled from the end
+    // so the walk below can go forward.
+    let width = new_changed.len() + 1;
+    let mut common = vec![0usize; (old_changed.len() + 1) * width];
+    for i in (0..old_changed.len()).rev() {
+        for j in (0..new_changed.len()).rev() {
+            common[i * width + j] = if old_changed[i] == new_changed[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                cmp::max(common[(i + 1) * width + j], common[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old_changed.len() || j < new_changed.len() {
+        if i < old_changed.len() && j < new_changed.len() && old_changed[i] == new_changed[j] {
+            i += 1;
+            j += 1;
+        } else if i < old_changed.len()
+            && (j == new_changed.len() || common[(i + 1) * width + j] >= common[i * width + j + 1])
+        {
+            lines.push(removed(i));
+            i += 1;
+        } else {
+            lines.push(added(j));
+            j += 1;
+        }
+    }
+    lines
+}
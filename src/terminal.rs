@@ -1,6 +1,7 @@
 use crossterm::cursor::{Hide, Show};
+use crossterm::event::{DisableFocusChange, EnableFocusChange};
 use crossterm::queue;
-use crossterm::style::Print;
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType};
 use crossterm::{cursor::MoveTo, terminal::size};
 use std::io::{self, stdout, Write};
@@ -8,17 +9,30 @@ use std::io::{self, stdout, Write};
 #[derive(Default)]
 pub struct Terminal {}
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+#[derive(Clone, Copy, Default)]
+pub struct Size {
+    pub width: usize,
+    pub height: usize,
+}
+
 impl Terminal {
+    pub fn size() -> Result<Size, io::Error> {
+        let (width, height) = size()?;
+        Ok(Size {
+            width: width as usize,
+            height: height as usize,
+        })
+    }
     pub fn move_cursor(position: Position) -> Result<(), io::Error> {
         let x = position.x as u16;
         let y = position.y as u16;
-        queue!(stdout(), MoveTo(x, y)).unwrap();
+        queue!(stdout(), MoveTo(x, y))?;
         Ok(())
     }
     pub fn hide_cursor() -> Result<(), io::Error> {
@@ -31,8 +45,8 @@ impl Terminal {
     }
     pub fn initialize() -> Result<(), io::Error> {
         enable_raw_mode()?;
+        queue!(stdout(), EnableFocusChange)?;
         Self::clear_screen()?;
-        //Self::draw_rows()?;
         Self::move_cursor(Position { x: 0, y: 0 })?;
         Self::execute()?;
         Ok(())
@@ -40,7 +54,28 @@ impl Terminal {
     pub fn clear_screen() -> Result<(), io::Error> {
         queue!(stdout(), Clear(ClearType::All))
     }
+    /// Points standard input back at the terminal after it was read to the
+    /// end, such as when a buffer was piped in.
+    #[cfg(unix)]
+    pub fn reattach_stdin() -> Result<(), io::Error> {
+        use std::os::fd::AsRawFd;
+        let tty = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")?;
+        if unsafe { libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    /// The console is read through its own handle, whatever standard input
+    /// was.
+    #[cfg(not(unix))]
+    pub fn reattach_stdin() -> Result<(), io::Error> {
+        Ok(())
+    }
     pub fn terminate() -> Result<(), io::Error> {
+        queue!(stdout(), DisableFocusChange)?;
         Self::execute()?;
         disable_raw_mode()?;
         Ok(())
@@ -53,6 +88,23 @@ impl Terminal {
         queue!(stdout(), Clear(ClearType::CurrentLine))?;
         Ok(())
     }
+    /// Clears from the cursor to the end of the line.
+    pub fn clear_until_newline() -> Result<(), io::Error> {
+        queue!(stdout(), Clear(ClearType::UntilNewLine))?;
+        Ok(())
+    }
+    pub fn set_bg_color(color: Color) -> Result<(), io::Error> {
+        queue!(stdout(), SetBackgroundColor(color))?;
+        Ok(())
+    }
+    pub fn set_fg_color(color: Color) -> Result<(), io::Error> {
+        queue!(stdout(), SetForegroundColor(color))?;
+        Ok(())
+    }
+    pub fn reset_colors() -> Result<(), io::Error> {
+        queue!(stdout(), ResetColor)?;
+        Ok(())
+    }
     pub fn execute() -> Result<(), io::Error> {
         stdout().flush()?;
         Ok(())
@@ -0,0 +1,297 @@
+use std::{cmp, mem};
+
+const MIN_SHARE: usize = 10;
+const MAX_SHARE: usize = 90;
+
+/// Part of the screen, by its top left corner and its size.
+#[derive(Clone, Copy, Default)]
+pub struct Area {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Which way a split divides its area.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// One window above the other.
+    Horizontal,
+    /// Windows side by side, with a separator column between them.
+    Vertical,
+}
+
+/// How the screen is shared between windows, which are numbered by their
+/// index in the editor's list. A split gives `share` percent of its area to
+/// `first` and the rest to `second`.
+pub enum Layout {
+    Window(usize),
+    Split {
+        direction: Direction,
+        share: usize,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /// The area of every window within `area`. A window's area includes its
+    /// status line at the bottom, but not the separator to its left.
+    pub fn areas(&self, area: Area) -> Vec<(usize, Area)> {
+        let mut areas = Vec::new();
+        self.collect_areas(area, &mut areas);
+        areas
+    }
+    fn collect_areas(&self, area: Area, areas: &mut Vec<(usize, Area)>) {
+        match self {
+            Layout::Window(window) => areas.push((*window, area)),
+            Layout::Split {
+                direction,
+                share,
+                first,
+                second,
+            } => {
+                let (first_area, second_area) = split_area(area, *direction, *share);
+                first.collect_areas(first_area, areas);
+                second.collect_areas(second_area, areas);
+            }
+        }
+    }
+    /// Splits `window` in `direction` and puts window `new` after it.
+    pub fn split(&mut self, window: usize, new: usize, direction: Direction) {
+        match self {
+            Layout::Window(found) if *found == window => {
+                *self = Layout::Split {
+                    direction,
+                    share: 50,
+                    first: Box::new(Layout::Window(window)),
+                    second: Box::new(Layout::Window(new)),
+                };
+            }
+            Layout::Window(_) => (),
+            Layout::Split { first, second, .. } => {
+                first.split(window, new, direction);
+                second.split(window, new, direction);
+            }
+        }
+    }
+    /// Takes `window` out, giving its area to the other side of its split,
+    /// and renumbers the windows after it.
+    pub fn remove(&mut self, window: usize) {
+        self.take_out(window);
+        self.renumber(window);
+    }
+    fn take_out(&mut self, window: usize) {
+        let Layout::Split { first, second, .. } = self else {
+            return;
+        };
+        let sibling = match (first.as_mut(), second.as_mut()) {
+            (Layout::Window(found), other) | (other, Layout::Window(found)) if *found == window => {
+                mem::replace(other, Layout::Window(window))
+            }
+            _ => {
+                first.take_out(window);
+                second.take_out(window);
+                return;
+            }
+        };
+        *self = sibling;
+    }
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Layout::Window(window) if *window > removed => *window -= 1,
+            Layout::Window(_) => (),
+            Layout::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+    /// Grows `window` by `by` percent of the split it is directly in, or
+    /// shrinks it when `by` is negative. Returns false if it is not split.
+    pub fn resize(&mut self, window: usize, by: isize) -> bool {
+        let Layout::Split {
+            share,
+            first,
+            second,
+            ..
+        } = self
+        else {
+            return false;
+        };
+        if first.resize(window, by) || second.resize(window, by) {
+            return true;
+        }
+        let change = match (first.as_ref(), second.as_ref()) {
+            (Layout::Window(found), _) if *found == window => by,
+            (_, Layout::Window(found)) if *found == window => -by,
+            _ => return false,
+        };
+        *share = share
+            .saturating_add_signed(change)
+            .clamp(MIN_SHARE, MAX_SHARE);
+        true
+    }
+}
+
+/// Divides `area` in `direction`, `share` percent to the first part. Each
+/// part keeps at least a line of text with its status line, or a column.
+fn split_area(area: Area, direction: Direction, share: usize) -> (Area, Area) {
+    match direction {
+        Direction::Horizontal => {
+            let first = cmp::min(
+                cmp::max(area.height * share / 100, 2),
+                area.height.saturating_sub(2),
+            );
+            (
+                Area {
+                    height: first,
+                    ..area
+                },
+                Area {
+                    y: area.y + first,
+                    height: area.height - first,
+                    ..area
+                },
+            )
+        }
+        Direction::Vertical => {
+            let available = area.width.saturating_sub(1);
+            let first = cmp::min(
+                cmp::max(available * share / 100, 1),
+                available.saturating_sub(1),
+            );
+            (
+                Area {
+                    width: first,
+                    ..area
+                },
+                Area {
+                    x: area.x + first + 1,
+                    width: available - first,
+                    ..area
+                },
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Area = Area {
+        x: 0,
+        y: 0,
+        width: 80,
+        height: 24,
+    };
+
+    /// Each window with its area as `(x, y, width, height)`.
+    fn areas(layout: &Layout, area: Area) -> Vec<(usize, (usize, usize, usize, usize))> {
+        layout
+            .areas(area)
+            .into_iter()
+            .map(|(window, area)| (window, (area.x, area.y, area.width, area.height)))
+            .collect()
+    }
+
+    #[test]
+    fn split_then_remove_either_side() {
+        for removed in [1, 0] {
+            let mut layout = Layout::Window(0);
+            layout.split(0, 1, Direction::Horizontal);
+            assert_eq!(
+                areas(&layout, SCREEN),
+                [(0, (0, 0, 80, 12)), (1, (0, 12, 80, 12))]
+            );
+            layout.remove(removed);
+            // The window left is numbered 0 and gets the whole screen back.
+            assert!(matches!(layout, Layout::Window(0)), "removed {removed}");
+            assert_eq!(areas(&layout, SCREEN), [(0, (0, 0, 80, 24))]);
+        }
+    }
+
+    #[test]
+    fn removing_a_middle_window_renumbers_the_rest() {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, Direction::Vertical);
+        layout.split(1, 2, Direction::Horizontal);
+        layout.split(2, 3, Direction::Horizontal);
+        assert_eq!(
+            areas(&layout, SCREEN)
+                .iter()
+                .map(|(window, _)| *window)
+                .collect::<Vec<_>>(),
+            [0, 1, 2, 3]
+        );
+
+        layout.remove(1);
+        assert_eq!(
+            areas(&layout, SCREEN),
+            [
+                (0, (0, 0, 39, 24)),
+                (1, (40, 0, 40, 12)),
+                (2, (40, 12, 40, 12)),
+            ]
+        );
+    }
+
+    #[test]
+    fn resizing_keeps_the_share_within_bounds() {
+        let screen = Area {
+            height: 100,
+            ..SCREEN
+        };
+        let heights = |layout: &Layout| {
+            areas(layout, screen)
+                .iter()
+                .map(|(_, (_, _, _, height))| *height)
+                .collect::<Vec<_>>()
+        };
+        let mut layout = Layout::Window(0);
+        assert!(!layout.resize(0, 10));
+        layout.split(0, 1, Direction::Horizontal);
+
+        assert!(layout.resize(0, 20));
+        assert_eq!(heights(&layout), [70, 30]);
+        assert!(layout.resize(0, 1000));
+        assert_eq!(heights(&layout), [MAX_SHARE, 100 - MAX_SHARE]);
+        // Growing the second window shrinks the first.
+        assert!(layout.resize(1, isize::MAX));
+        assert_eq!(heights(&layout), [MIN_SHARE, 100 - MIN_SHARE]);
+        assert!(layout.resize(0, isize::MIN));
+        assert_eq!(heights(&layout), [MIN_SHARE, 100 - MIN_SHARE]);
+        assert!(!layout.resize(2, 10));
+    }
+
+    #[test]
+    fn tiny_areas_keep_minimum_sizes() {
+        let tiny = Area {
+            x: 5,
+            y: 3,
+            width: 3,
+            height: 4,
+        };
+        for share in [MIN_SHARE, MAX_SHARE] {
+            let (first, second) = split_area(tiny, Direction::Horizontal, share);
+            assert_eq!((first.y, first.height), (3, 2));
+            assert_eq!((second.y, second.height), (5, 2));
+            let (first, second) = split_area(tiny, Direction::Vertical, share);
+            assert_eq!((first.x, first.width), (5, 1));
+            // One column between them is the separator.
+            assert_eq!((second.x, second.width), (7, 1));
+        }
+
+        // Too small for both, the sizes still add up without underflowing.
+        let area = Area {
+            width: 1,
+            height: 1,
+            ..tiny
+        };
+        let (first, second) = split_area(area, Direction::Horizontal, 50);
+        assert_eq!(first.height + second.height, 1);
+        let (first, second) = split_area(area, Direction::Vertical, 50);
+        assert_eq!(first.width + second.width, 0);
+    }
+}